@@ -0,0 +1,56 @@
+// color() with colors on, as the CLI runs a file in a terminal, and off, as everywhere else
+// does. Run it with
+//   cargo run --example colors
+// It exits with an error if any output isn't what's expected
+
+use finnlang::{run_finn_code, run_finn_code_with_options, RunOptions};
+
+const SOURCE: &str = "woof(color(\"hi\", \"red\"));\nwoof(color(42, \"green\") + \"!\");";
+
+fn main() {
+    // Errors come back as values, so skip Rust's default panic message
+    std::panic::set_hook(Box::new(|_| {}));
+
+    let mut failed = false;
+    let mut check = |what: &str, passed: bool| {
+        println!("{}: {}", if passed { "ok" } else { "FAIL" }, what);
+        failed |= !passed;
+    };
+
+    let with_colors = |colors| RunOptions {
+        colors,
+        ..RunOptions::default()
+    };
+    let stdout = |colors| {
+        run_finn_code_with_options(SOURCE, &with_colors(colors))
+            .0
+            .ok()
+            .map(|output| output.stdout)
+    };
+    check(
+        "colors on wraps the text in escape codes",
+        stdout(true).as_deref() == Some("\x1b[31mhi\x1b[0m\n\x1b[32m42\x1b[0m!"),
+    );
+    check(
+        "colors off gives the plain text",
+        stdout(false).as_deref() == Some("hi\n42!"),
+    );
+
+    // The server and library runs never turn colors on
+    check(
+        "run_finn_code gives the plain text",
+        run_finn_code(SOURCE).ok().as_deref() == Some("hi\n42!"),
+    );
+
+    check(
+        "an unknown color is an error either way",
+        run_finn_code_with_options("woof(color(\"hi\", \"purple\"));", &with_colors(true))
+            .0
+            .is_err()
+            && run_finn_code("woof(color(\"hi\", \"purple\"));").is_err(),
+    );
+
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,67 @@
+// Registering a Rust function for FinnLang programs to call. Run it with
+//   cargo run --example native_builtin
+// It exits with an error if any of the programs below doesn't do what's expected
+
+use finnlang::{NativeRunner, Value};
+
+fn main() {
+    let mut runner = NativeRunner::new();
+    runner.register_builtin("double_it", |args| match args.as_slice() {
+        [Value::Int(n)] => n
+            .checked_mul(2)
+            .map(Value::Int)
+            .ok_or_else(|| format!("{} is too big to double", n)),
+        [Value::Double(d)] => Ok(Value::Double(d * 2.0)),
+        _ => Err(format!("expects one number, got {} arguments", args.len())),
+    });
+
+    let checks = [
+        // Called like any other function, including from inside a funct
+        (
+            "woof(double_it(21));\nfunct quadruple(n: int): int { return double_it(double_it(n)); }\nwoof(quadruple(5));",
+            Ok("42\n20"),
+        ),
+        // Builtins that take a function name can use it too
+        ("woof(max([3, -7, 2], \"double_it\"));", Ok("3")),
+        // has_builtin knows about it alongside the built-in ones
+        (
+            "woof(has_builtin(\"double_it\"));\nwoof(has_builtin(\"clamp\"));\nwoof(has_builtin(\"triple_it\"));",
+            Ok("true\ntrue\nfalse"),
+        ),
+        // An Err from Rust is a runtime error
+        (
+            "woof(double_it(\"finn\"));",
+            Err("Runtime Error: double_it: expects one number, got 1 arguments"),
+        ),
+        // The program can't replace it with its own definition
+        (
+            "funct double_it(n: int): int { return n + n; }",
+            Err("Runtime Error: Cannot define function double_it: the program running this code already provides one with that name"),
+        ),
+    ];
+
+    let mut failed = false;
+    for (source, expected) in checks {
+        let actual = runner
+            .run(source)
+            .map(|output| output.stdout)
+            .map_err(|error| error.to_string());
+        let matches = match (&actual, expected) {
+            (Ok(stdout), Ok(want)) => stdout == want,
+            (Err(error), Err(want)) => error == want,
+            _ => false,
+        };
+        if matches {
+            println!("ok: {}", source.lines().next().unwrap_or_default());
+        } else {
+            println!(
+                "FAIL: {}\n  expected {:?}\n  got {:?}",
+                source, expected, actual
+            );
+            failed = true;
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
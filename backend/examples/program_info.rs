@@ -0,0 +1,85 @@
+// Parsing a program without running it, and what the resulting Program holds. Run it with
+//   cargo run --example program_info
+// It exits with an error if anything below isn't what's expected
+
+use finnlang::{parse_finn_code, Stmt, SymbolKind};
+
+const SOURCE: &str = "funct area(w: int, h: int): int {
+    return abs(w) * abs(h);
+}
+let width = 3, height = 4;
+let unused = 1;
+let = 5;
+woof(area(width, height));
+";
+
+fn main() {
+    // Errors come back as values, so skip Rust's default panic message
+    std::panic::set_hook(Box::new(|_| {}));
+
+    let program = parse_finn_code(SOURCE).expect("the program should parse");
+    let mut failed = false;
+    let mut check = |what: &str, passed: bool| {
+        println!("{}: {}", if passed { "ok" } else { "FAIL" }, what);
+        failed |= !passed;
+    };
+
+    // `let = 5;` is skipped, leaving the `5;` after it as a statement of its own
+    let lines: Vec<usize> = program.statements.iter().map(|(line, _)| *line).collect();
+    check("statements and their lines", lines == [1, 4, 5, 6, 7]);
+    check(
+        "the last statement is the woof",
+        matches!(program.statements.last(), Some((_, Stmt::Print(_)))),
+    );
+    check("source length", program.source_len == SOURCE.len());
+
+    let symbols: Vec<(&str, SymbolKind, usize)> = program
+        .symbols
+        .iter()
+        .map(|symbol| (symbol.name.as_str(), symbol.kind, symbol.line))
+        .collect();
+    check(
+        "top-level symbols",
+        symbols
+            == [
+                ("area", SymbolKind::Function, 1),
+                ("width", SymbolKind::Variable, 4),
+                ("height", SymbolKind::Variable, 4),
+                ("unused", SymbolKind::Variable, 5),
+            ],
+    );
+    check(
+        "looking up a symbol",
+        program.symbol("height").map(|symbol| symbol.line) == Some(4),
+    );
+
+    // Calls in function bodies count as well as calls at the top level
+    let calls = program.call_counts();
+    check(
+        "call counts",
+        calls.len() == 2 && calls.get("abs") == Some(&2) && calls.get("area") == Some(&1),
+    );
+
+    // Both the parser's warning and the static checks' end up in one list, in line order
+    let diagnostics: Vec<String> = program.diagnostics.iter().map(|w| w.to_string()).collect();
+    for diagnostic in &diagnostics {
+        println!("  {}", diagnostic);
+    }
+    check(
+        "diagnostics from parsing and checking",
+        diagnostics.len() == 2
+            && diagnostics[0].contains("unused")
+            && diagnostics[0].ends_with("(line 5)")
+            && diagnostics[1].starts_with("Skipped Assign at column 5")
+            && diagnostics[1].ends_with("(line 6)"),
+    );
+
+    check(
+        "a parse error is still an error",
+        parse_finn_code("let x = 5 @ 2;").is_err(),
+    );
+
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,78 @@
+// Stopping programs that would never end on their own with RunOptions::stop, the way the
+// server does when a run times out. Run it with
+//   cargo run --example stopping
+// It exits with an error if any of the programs below doesn't stop soon after being told to
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use finnlang::{run_finn_code_best_effort_with_options, run_finn_code_with_options, RunOptions};
+
+const STOPPED: &str = "Runtime Error: Code execution was stopped";
+
+fn main() {
+    // Errors come back as values, so skip Rust's default panic message
+    std::panic::set_hook(Box::new(|_| {}));
+
+    let programs = [
+        ("a while loop", "while (true) {}"),
+        ("a for loop", "for (;;) {}"),
+        (
+            "a for-each loop over a long range",
+            "for (i in range(1000000000)) {}",
+        ),
+        // A tail call doesn't nest, so without the flag this never runs out of stack
+        (
+            "a tail call forever",
+            "funct spin(n: int): int { return spin(n + 1); }\nspin(0);",
+        ),
+        // The catch would swallow any other error and go round again
+        (
+            "a loop catching every error",
+            "while (true) { try { while (true) {} } catch (e) { woof(e); } }",
+        ),
+    ];
+
+    let mut failed = false;
+    for (what, source) in programs {
+        let stop = Arc::new(AtomicBool::new(false));
+        let options = RunOptions {
+            stop: Some(Arc::clone(&stop)),
+            ..RunOptions::default()
+        };
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            stop.store(true, Ordering::Relaxed);
+        });
+
+        let start = Instant::now();
+        let error = run_finn_code_with_options(source, &options)
+            .0
+            .err()
+            .map(|error| error.to_string());
+        let best_effort = run_finn_code_best_effort_with_options(source, &options)
+            .error
+            .map(|error| error.to_string());
+        let elapsed = start.elapsed();
+        stopper.join().expect("the stopping thread shouldn't fail");
+
+        // The flag stays set, so the best-effort run stops straight away
+        let passed = error.as_deref() == Some(STOPPED)
+            && best_effort.as_deref() == Some(STOPPED)
+            && elapsed < Duration::from_secs(2);
+        if passed {
+            println!("ok: {}", what);
+        } else {
+            println!(
+                "FAIL: {}\n  got {:?} and {:?} after {:?}",
+                what, error, best_effort, elapsed
+            );
+            failed = true;
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -1,7 +1,7 @@
 // Fix linting warnings for unused code during development
 #![allow(dead_code)]
 // Represents the basic data types supported by the language
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Type {
     // Integer
     Int,
@@ -11,20 +11,61 @@ pub enum Type {
     String,
     // Floating-point
     Double,
+    // Array with elements of the given type, written [int], [[string]], ...
+    Array(Box<Type>),
+    // A shared reference made with ref(x), whatever it holds
+    Ref,
+}
+
+// Types print the way they're written in source, e.g. [[int]]
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Bool => write!(f, "bool"),
+            Type::String => write!(f, "string"),
+            Type::Double => write!(f, "double"),
+            Type::Array(element) => write!(f, "[{}]", element),
+            Type::Ref => write!(f, "ref"),
+        }
+    }
 }
 
 // Represents different kinds of statements in the language
 #[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum Stmt {
-    // Variable declaration with a type, variable name, and initial expression
-    Let(Type, String, Expr),
+    // Variable declaration with an optional type, variable name, initial expression, and the
+    // line the name is on. An untyped let takes its type from the value of the initializer
+    // when it runs
+    Let(Option<Type>, String, Expr, usize),
+
+    // once x = expr; declares a variable like let, but only evaluates the initializer the first
+    // time it's reached; later times (in a loop or another call) reuse that value. The id,
+    // unique to each once in the source, is what the value is remembered by. The last field
+    // is the line the name is on, like Let's
+    Once(usize, Option<Type>, String, Expr, usize),
+
+    // Array destructuring declaration: let [a, b] = expr; with the line the names start on
+    LetDestructure(Vec<String>, Expr, usize),
 
-    // Assignment of a new value to an existing variable
-    Assign(String, Expr),
+    // Several declarations in one statement: let a = 1, b = a + 1; holds one Let per variable,
+    // run in order in the current scope
+    LetGroup(Vec<Stmt>),
+
+    // Assignment of a new value to a variable, or to an element or entry inside one
+    Assign(LValue, Expr),
+
+    // x += y (and -=, *=, /=, %=): like Assign with the value x + y, except that the index
+    // expressions in x are evaluated once, for both reading and writing the place
+    CompoundAssign(LValue, Expr),
 
     // Print statement to output the value of an expression
     Print(Expr),
 
+    // Print statement that writes to the error stream instead (howl)
+    PrintErr(Expr),
+
     // While loop with a condition expression and a block of statements to execute repeatedly
     While(Expr, Vec<Stmt>),
 
@@ -36,17 +77,91 @@ pub enum Stmt {
         Vec<Stmt>,
     ),
 
+    // For-each loop: for (x in items) or for (key, value in map). The second variable is
+    // optional; the subject is evaluated once before the loop starts
+    ForEach(String, Option<String>, Expr, Vec<Stmt>),
+
     // If/Elif/Else statement
     If(Expr, Vec<Stmt>, Vec<(Expr, Vec<Stmt>)>, Option<Vec<Stmt>>),
 
-    // Function definition: name, parameters (name, type), return type, body
-    FunctionDef(String, Vec<(String, Type)>, Option<Type>, Vec<Stmt>),
+    // Function definition: name, parameters (name, type), optional trailing rest parameter,
+    // return type, body
+    FunctionDef(
+        String,
+        Vec<(String, Type)>,
+        Option<(String, Type)>,
+        Option<Type>,
+        Vec<Stmt>,
+    ),
 
     // Return statement with optional expression
     Return(Option<Expr>),
 
     // Expression statement (for standalone expressions like function calls)
     ExprStmt(Expr),
+
+    // debug; shows the variables in scope when the program runs in debug mode, and does
+    // nothing otherwise
+    Debug,
+
+    // Bare block { ... } that runs its statements in a fresh scope
+    Block(Vec<Stmt>),
+
+    // assert(condition) or assert(condition, message)
+    Assert(Expr, Option<Expr>),
+
+    // assert_eq(actual, expected) or assert_eq(actual, expected, message)
+    AssertEq(Expr, Expr, Option<Expr>),
+
+    // typematch subject { int: { ... } string: { ... } default: { ... } }: runs the block for
+    // the subject's type (a name from Value::type_name), or the default block if none matches
+    TypeMatch(Expr, Vec<(String, Vec<Stmt>)>, Option<Vec<Stmt>>),
+
+    // try { ... } catch (e) { ... } finally { ... }, where at least one of catch/finally is present.
+    // The catch clause binds the error message to its variable
+    Try(Vec<Stmt>, Option<(String, Vec<Stmt>)>, Option<Vec<Stmt>>),
+}
+
+impl Stmt {
+    // A short name for the kind of statement, mostly the keyword it starts with
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Stmt::Let(..) | Stmt::LetDestructure(..) | Stmt::LetGroup(_) => "let",
+            Stmt::Assign(..) | Stmt::CompoundAssign(..) => "assignment",
+            Stmt::Print(_) => "woof",
+            Stmt::PrintErr(_) => "howl",
+            Stmt::While(..) => "while",
+            Stmt::For(..) | Stmt::ForEach(..) => "for",
+            Stmt::If(..) => "if",
+            Stmt::FunctionDef(..) => "funct",
+            Stmt::Return(_) => "return",
+            Stmt::ExprStmt(_) => "expression",
+            Stmt::Block(_) => "block",
+            Stmt::Assert(..) => "assert",
+            Stmt::AssertEq(..) => "assert_eq",
+            Stmt::Try(..) => "try",
+            Stmt::Once(..) => "once",
+            Stmt::TypeMatch(..) => "typematch",
+            Stmt::Debug => "debug",
+        }
+    }
+}
+
+// The target of an assignment: a variable followed by any chain of indices and fields,
+// e.g. `x`, `arr[0]`, `m["key"]` or `p.items[0].name`
+#[derive(Debug, Clone)]
+pub struct LValue {
+    pub name: String,
+    pub path: Vec<Accessor>,
+}
+
+// One step into an array or map on the way to the place being assigned
+#[derive(Debug, Clone)]
+pub enum Accessor {
+    // [expr]: an array element or a map entry
+    Index(Expr),
+    // .name: the map entry with that key
+    Field(String),
 }
 
 // Represents expressions that can be evaluated to produce values
@@ -64,6 +179,9 @@ pub enum Expr {
     // Literal floating point number
     Double(f64),
 
+    // Literal null
+    Null,
+
     // Variable reference by name
     Var(String),
 
@@ -78,8 +196,13 @@ pub enum Expr {
     Mod(Box<Expr>, Box<Expr>),
     // Arrays
     ArrayLiteral(Vec<Expr>),
+    // Map literal: { key: value, "other key": value }
+    MapLiteral(Vec<(String, Expr)>),
+    // ...expr, expands an array in place; only valid in call arguments and array literals
+    Spread(Box<Expr>),
     Index(Box<Expr>, Box<Expr>),
-    AssignIndex(Box<Expr>, Box<Expr>, Box<Expr>), // arr[index] = value
+    // m.name, the same as m["name"]
+    Field(Box<Expr>, String),
     // Comparison operations (equality, inequality, less than, greater than, etc.)
     Eq(Box<Expr>, Box<Expr>),
     LessThan(Box<Expr>, Box<Expr>),
@@ -97,4 +220,7 @@ pub enum Expr {
 
     // Logical negation (not)
     Not(Box<Expr>),
+
+    // Defaulting a ?? b: a if it's truthy, otherwise b (only evaluated when needed)
+    Coalesce(Box<Expr>, Box<Expr>),
 }
@@ -1,48 +1,254 @@
 use axum::{routing::post, Json, Router};
-use finnlang::run_finn_code;
+use finnlang::{
+    parse_finn_code, run_finn_code_best_effort_with_options, run_finn_code_with_options,
+    tokenize_finn_code, FinnLangError, RunOptions, RUN_STACK_SIZE,
+};
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
-use tower_http::cors::{Any, CorsLayer};
+use tokio::sync::oneshot;
 use tokio::time::timeout;
+use tower_http::cors::{Any, CorsLayer};
 
 #[derive(Deserialize)]
 struct RunRequest {
     code: String,
+    // Keep the output printed before a runtime error and report the line it happened on
+    #[serde(default)]
+    best_effort: bool,
+    // Check types before running, and don't run the program if there are type errors
+    #[serde(default)]
+    typecheck: bool,
+    // What read_line() gives the program, in place of stdin
+    #[serde(default)]
+    input: Option<RunInput>,
+}
+
+// Input can be sent as one string, which is split into lines, or as the lines themselves
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RunInput {
+    Text(String),
+    Lines(Vec<String>),
+}
+
+impl RunInput {
+    fn into_lines(self) -> Vec<String> {
+        match self {
+            RunInput::Text(text) => text.lines().map(str::to_string).collect(),
+            RunInput::Lines(lines) => lines,
+        }
+    }
 }
 
 #[derive(Serialize)]
 struct RunResponse {
     output: String,
+    // Anything the program wrote with howl()
+    stderr: String,
     error: Option<String>,
+    // Line of the statement that raised `error`, in best-effort mode
+    error_line: Option<usize>,
     success: bool,
+    // Likely mistakes found before running (unused or shadowed variables, unreachable code).
+    // The program runs either way
+    warnings: Vec<WarningResponse>,
+    // With `typecheck`, every type error found before running, in the same form as warnings.
+    // If there are any, the program didn't run and `error` lists them all too
+    type_errors: Vec<WarningResponse>,
 }
 
-async fn run_code(Json(payload): Json<RunRequest>) -> Json<RunResponse> {
-    // Add timeout to prevent infinite loops
-    let result = timeout(Duration::from_secs(5), async {
-        run_finn_code(&payload.code)
-    }).await;
-    
-    match result {
-        Ok(Ok(output)) => Json(RunResponse {
-            output,
+#[derive(Serialize)]
+struct WarningResponse {
+    message: String,
+    // Line of the top-level statement the warning is about
+    line: usize,
+}
+
+#[derive(Deserialize)]
+struct TokensRequest {
+    code: String,
+}
+
+#[derive(Serialize)]
+struct TokensResponse {
+    tokens: Vec<TokenResponse>,
+    // Set if the code has a character the lexer can't read; `tokens` is empty then
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TokenResponse {
+    kind: String,
+    text: String,
+    line: usize,
+    column: usize,
+    // Character offsets into `code`: the token is code[start..end]
+    start: usize,
+    end: usize,
+}
+
+// Split code into tokens with their exact source ranges, e.g. for editor highlighting
+async fn tokens(Json(payload): Json<TokensRequest>) -> Json<TokensResponse> {
+    match tokenize_finn_code(&payload.code) {
+        Ok(tokens) => Json(TokensResponse {
+            tokens: tokens
+                .into_iter()
+                .map(|token| TokenResponse {
+                    kind: token.kind,
+                    text: token.text,
+                    line: token.line,
+                    column: token.column,
+                    start: token.start,
+                    end: token.end,
+                })
+                .collect(),
             error: None,
-            success: true,
         }),
-        Ok(Err(error)) => Json(RunResponse {
-            output: String::new(),
+        Err(error) => Json(TokensResponse {
+            tokens: Vec::new(),
             error: Some(error.to_string()),
-            success: false,
-        }),
-        Err(_) => Json(RunResponse {
-            output: String::new(),
-            error: Some("Code execution timed out (5 seconds)".to_string()),
-            success: false,
         }),
     }
 }
 
+// How long a program may run before the request gives up on it
+const RUN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Stack size for the threads programs run on: RUN_STACK_SIZE, or FINN_STACK_MB megabytes
+fn run_stack_size() -> usize {
+    std::env::var("FINN_STACK_MB")
+        .ok()
+        .and_then(|mb| mb.parse::<usize>().ok())
+        .map_or(RUN_STACK_SIZE, |mb| mb * 1024 * 1024)
+}
+
+// Run `work` on a thread of its own with a large stack and wait up to RUN_TIMEOUT for its
+// result. Deep recursion gets far more room than on a server worker thread, and since the
+// handler only awaits a channel, the timeout fires even while the program is still going.
+// `work` is given a stop flag that's set when the time runs out; the interpreter checks it in
+// loops and calls, so a program that times out stops soon after instead of running on
+async fn run_on_worker<T: Send + 'static>(
+    work: impl FnOnce(Arc<AtomicBool>) -> T + Send + 'static,
+) -> Result<T, String> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let worker_stop = Arc::clone(&stop);
+    let (sender, receiver) = oneshot::channel();
+    let spawned = thread::Builder::new()
+        .name("finn-run".to_string())
+        .stack_size(run_stack_size())
+        .spawn(move || {
+            // The handler may have timed out and stopped listening, which is fine
+            let _ = sender.send(work(worker_stop));
+        });
+    if let Err(error) = spawned {
+        return Err(format!("Couldn't start the program: {}", error));
+    }
+
+    match timeout(RUN_TIMEOUT, receiver).await {
+        Ok(Ok(result)) => Ok(result),
+        // The thread ended without a result. The interpreter catches its own errors, so
+        // this only happens if something outside it panicked
+        Ok(Err(_)) => Err("Code execution stopped unexpectedly".to_string()),
+        Err(_) => {
+            stop.store(true, Ordering::Relaxed);
+            Err(format!(
+                "Code execution timed out ({} seconds)",
+                RUN_TIMEOUT.as_secs()
+            ))
+        }
+    }
+}
+
+// The response for a run that failed before producing any output
+fn failed_run(
+    error: String,
+    warnings: Vec<WarningResponse>,
+    type_errors: Vec<WarningResponse>,
+) -> RunResponse {
+    RunResponse {
+        output: String::new(),
+        stderr: String::new(),
+        error: Some(error),
+        error_line: None,
+        success: false,
+        warnings,
+        type_errors,
+    }
+}
+
+// Parsing and checking recurse through the code like running it does, so the whole request is
+// handled on the worker, with its stack and under its timeout
+async fn run_code(Json(payload): Json<RunRequest>) -> Json<RunResponse> {
+    match run_on_worker(move |stop| run_request(payload, stop)).await {
+        Ok(response) => Json(response),
+        Err(error) => Json(failed_run(error, Vec::new(), Vec::new())),
+    }
+}
+
+// Check and run the code from a request, stopping if `stop` is set
+fn run_request(payload: RunRequest, stop: Arc<AtomicBool>) -> RunResponse {
+    // Code that doesn't parse has no warnings or type errors; the parse error comes back from
+    // the run instead
+    let (warnings, type_errors) = match parse_finn_code(&payload.code) {
+        Ok(program) => (program.diagnostics, program.type_errors),
+        Err(_) => (Vec::new(), Vec::new()),
+    };
+    let warnings: Vec<WarningResponse> = warnings
+        .into_iter()
+        .map(|warning| WarningResponse {
+            message: warning.message,
+            line: warning.line,
+        })
+        .collect();
+
+    if payload.typecheck && !type_errors.is_empty() {
+        let error = FinnLangError::TypeErrors(type_errors.clone()).to_string();
+        let type_errors = type_errors
+            .into_iter()
+            .map(|error| WarningResponse {
+                message: error.message,
+                line: error.line,
+            })
+            .collect();
+        return failed_run(error, warnings, type_errors);
+    }
+
+    let options = RunOptions {
+        input: payload.input.map_or_else(Vec::new, RunInput::into_lines),
+        stop: Some(stop),
+        ..RunOptions::default()
+    };
+    if payload.best_effort {
+        let run = run_finn_code_best_effort_with_options(&payload.code, &options);
+        return RunResponse {
+            output: run.output.stdout,
+            stderr: run.output.stderr,
+            success: run.error.is_none(),
+            error: run.error.map(|error| error.to_string()),
+            error_line: run.error_line,
+            warnings,
+            type_errors: Vec::new(),
+        };
+    }
+
+    match run_finn_code_with_options(&payload.code, &options).0 {
+        Ok(output) => RunResponse {
+            output: output.stdout,
+            stderr: output.stderr,
+            error: None,
+            error_line: None,
+            success: true,
+            warnings,
+            type_errors: Vec::new(),
+        },
+        Err(error) => failed_run(error.to_string(), warnings, Vec::new()),
+    }
+}
+
 #[tokio::main]
 async fn main() {
     // Get port from environment variable (Render provides this) or default to 3000
@@ -57,7 +263,10 @@ async fn main() {
         .allow_methods(Any) // Allow all methods: POST, OPTIONS, etc.
         .allow_headers(Any); // Allow all headers
 
-    let app = Router::new().route("/run", post(run_code)).layer(cors); // Attach CORS middleware
+    let app = Router::new()
+        .route("/run", post(run_code))
+        .route("/tokens", post(tokens))
+        .layer(cors); // Attach CORS middleware
     
     // Bind to all interfaces (0.0.0.0) so Render can access it
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
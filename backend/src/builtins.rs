@@ -0,0 +1,1273 @@
+#![allow(dead_code)]
+// Built-in functions that FinnLang programs can call without defining them
+
+use num_bigint::{BigInt, Sign};
+use num_traits::{Signed, ToPrimitive, Zero};
+use regex::Regex;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+
+use crate::ast::Expr;
+use crate::interpreter::{
+    compare_values, did_you_mean, format_double, int_out_of_range, int_overflow, int_value,
+    values_equal, Interpreter, Value, MAX_DISPLAY_DEPTH,
+};
+use crate::json;
+
+// Names of every built-in function, checked after user-defined functions
+pub const BUILTINS: &[&str] = &[
+    "format_number",
+    "clamp",
+    "insert",
+    "remove_at",
+    "slice_assign",
+    "matches",
+    "find_all",
+    "split",
+    "split_regex",
+    "version",
+    "has_builtin",
+    "type_of",
+    "keys",
+    "values",
+    "has_key",
+    "pretty",
+    "is_int",
+    "is_double",
+    "is_string",
+    "is_bool",
+    "is_array",
+    "is_map",
+    "is_null",
+    "concat",
+    "fill",
+    "pad_left",
+    "pad_right",
+    "center",
+    "safe_div",
+    "mod_floor",
+    "floor_div",
+    "abs",
+    "sign",
+    "gcd",
+    "lcm",
+    "find",
+    "any",
+    "all",
+    "json_parse",
+    "json_stringify",
+    "range",
+    "min",
+    "max",
+    "trim",
+    "trim_start",
+    "trim_end",
+    "strip_prefix",
+    "strip_suffix",
+    "int",
+    "abs_diff",
+    "approx_eq",
+    "starts_with",
+    "ends_with",
+    "replace",
+    "sum",
+    "slice",
+    "substring",
+    "print_with",
+    "woof_lines",
+    "join",
+    "to_chars",
+    "from_chars",
+    "compare",
+    "equals",
+    "contains",
+    "index_of",
+    "count",
+    "group_by",
+    "frequencies",
+    "ref",
+    "read_line",
+    "read_file",
+    "write_file",
+    "color",
+];
+
+impl Interpreter {
+    // Call a built-in function by name with unevaluated argument expressions
+    pub(crate) fn call_builtin(&mut self, name: &str, args: Vec<Expr>) -> Value {
+        // Mutating builtins write their result back to this argument if it's a variable
+        let target = args.first().cloned();
+        let values = self.eval_list(args);
+
+        match name {
+            // format_number(n) or format_number(n, separator)
+            "format_number" => {
+                check_arity_range(name, &values, 1, 2);
+                let separator = values.get(1).map_or(",", |v| v.expect_str(name));
+                Value::Str(format_number(&values[0], separator))
+            }
+            // clamp(x, lo, hi)
+            "clamp" => {
+                check_arity(name, &values, 3);
+                let (x, lo, hi) = (&values[0], &values[1], &values[2]);
+                if compare_numbers(name, lo, hi) == Ordering::Greater {
+                    panic!(
+                        "clamp lower bound {} is greater than upper bound {}",
+                        lo, hi
+                    );
+                }
+                if compare_numbers(name, x, lo) == Ordering::Less {
+                    lo.clone()
+                } else if compare_numbers(name, x, hi) == Ordering::Greater {
+                    hi.clone()
+                } else {
+                    x.clone()
+                }
+            }
+            // insert(arr, index, value) shifts later elements right
+            "insert" => {
+                check_arity(name, &values, 3);
+                let mut arr = values[0].expect_array(name).to_vec();
+                let index = expect_index(name, &values[1], arr.len() + 1);
+                arr.insert(index, values[2].clone());
+                let result = Value::Array(arr);
+                self.write_back(target, result.clone());
+                result
+            }
+            // remove_at(arr, index) removes and returns the element
+            "remove_at" => {
+                check_arity(name, &values, 2);
+                let mut arr = values[0].expect_array(name).to_vec();
+                let index = expect_index(name, &values[1], arr.len());
+                let removed = arr.remove(index);
+                self.write_back(target, Value::Array(arr));
+                removed
+            }
+            // slice_assign(arr, start, end, newArr) replaces arr[start..end] with newArr
+            "slice_assign" => {
+                check_arity(name, &values, 4);
+                let mut arr = values[0].expect_array(name).to_vec();
+                let start = expect_index(name, &values[1], arr.len() + 1);
+                let end = expect_index(name, &values[2], arr.len() + 1);
+                if start > end {
+                    panic!("slice_assign start {} is after end {}", start, end);
+                }
+                let replacement = values[3].expect_array(name).to_vec();
+                arr.splice(start..end, replacement);
+                let result = Value::Array(arr);
+                self.write_back(target, result.clone());
+                result
+            }
+            // matches(s, pattern) is true if the pattern matches anywhere in s
+            "matches" => {
+                check_arity(name, &values, 2);
+                let s = values[0].expect_str(name);
+                let re = compile_regex(name, &values[1]);
+                Value::Bool(re.is_match(s))
+            }
+            // find_all(s, pattern) returns every non-overlapping match
+            "find_all" => {
+                check_arity(name, &values, 2);
+                let s = values[0].expect_str(name);
+                let re = compile_regex(name, &values[1]);
+                Value::Array(
+                    re.find_iter(s)
+                        .map(|m| Value::Str(m.as_str().to_string()))
+                        .collect(),
+                )
+            }
+            // split(s, sep) or split(s, sep, limit) breaks s at each sep. With a limit, only the
+            // first `limit` seps split and the rest of s is kept whole in the last piece
+            "split" => {
+                check_arity_range(name, &values, 2, 3);
+                let s = values[0].expect_str(name);
+                let sep = values[1].expect_str(name);
+                // An empty string occurs everywhere, so there's no sensible answer
+                if sep.is_empty() {
+                    panic!("split can't split on an empty string, use to_chars instead");
+                }
+                let pieces: Vec<&str> = match values.get(2) {
+                    Some(limit) => s.splitn(split_limit(name, limit), sep).collect(),
+                    None => s.split(sep).collect(),
+                };
+                self.split_result(s, pieces)
+            }
+            // split_regex(s, pattern) or split_regex(s, pattern, limit) is split with the pieces
+            // separated by matches of a pattern instead of a fixed string
+            "split_regex" => {
+                check_arity_range(name, &values, 2, 3);
+                let s = values[0].expect_str(name);
+                let re = compile_regex(name, &values[1]);
+                let pieces: Vec<&str> = match values.get(2) {
+                    Some(limit) => re.splitn(s, split_limit(name, limit)).collect(),
+                    None => re.split(s).collect(),
+                };
+                self.split_result(s, pieces)
+            }
+            // version() is the interpreter's crate version, e.g. "0.1.0"
+            "version" => {
+                check_arity(name, &values, 0);
+                Value::Str(env!("CARGO_PKG_VERSION").to_string())
+            }
+            // has_builtin(name) checks whether a builtin with that name exists, counting the
+            // ones the program running this code registered from Rust
+            "has_builtin" => {
+                check_arity(name, &values, 1);
+                let builtin = values[0].expect_str(name);
+                Value::Bool(BUILTINS.contains(&builtin) || self.has_native(builtin))
+            }
+            // type_of(x) is the name of x's runtime type, e.g. "double"
+            "type_of" => {
+                check_arity(name, &values, 1);
+                Value::Str(values[0].type_name().to_string())
+            }
+            // keys(m) is an array of m's keys, in insertion order
+            "keys" => {
+                check_arity(name, &values, 1);
+                let entries = values[0].expect_map(name);
+                Value::Array(entries.iter().map(|(k, _)| Value::Str(k.clone())).collect())
+            }
+            // values(m) is an array of m's values, in insertion order
+            "values" => {
+                check_arity(name, &values, 1);
+                let entries = values[0].expect_map(name);
+                Value::Array(entries.iter().map(|(_, v)| v.clone()).collect())
+            }
+            // has_key(m, key) checks whether m has an entry for key
+            "has_key" => {
+                check_arity(name, &values, 2);
+                let entries = values[0].expect_map(name);
+                let key = values[1].expect_str(name);
+                Value::Bool(entries.iter().any(|(k, _)| k == key))
+            }
+            // pretty(x) renders x as indented JSON-style text
+            "pretty" => {
+                check_arity(name, &values, 1);
+                let mut out = String::new();
+                write_pretty(&values[0], 0, &mut out);
+                Value::Str(out)
+            }
+            // is_int(x), is_double(x), ... check x's type
+            "is_int" | "is_double" | "is_string" | "is_bool" | "is_array" | "is_map"
+            | "is_null" => {
+                check_arity(name, &values, 1);
+                Value::Bool(name["is_".len()..] == *values[0].type_name())
+            }
+            // concat(a, b, ...) joins any number of values of any type into one string
+            "concat" => {
+                let joined: String = values.iter().map(|v| v.to_string()).collect();
+                self.track_allocation(joined.len());
+                Value::Str(joined)
+            }
+            // print_with(sep, end, a, b, ...) prints the values like woof, with sep between them
+            // and end after the last one instead of a newline
+            "print_with" => {
+                if values.len() < 2 {
+                    panic!(
+                        "Function {} expects at least 2 arguments, got {}",
+                        name,
+                        values.len()
+                    );
+                }
+                let separator = values[0].expect_str(name);
+                let end = values[1].expect_str(name);
+                let printed: Vec<String> = values[2..].iter().map(|v| v.to_string()).collect();
+                self.write_output(&(printed.join(separator) + end));
+                Value::Null
+            }
+            // woof_lines(arr) prints each element like woof, one per line
+            "woof_lines" => {
+                check_arity(name, &values, 1);
+                for element in values[0].expect_array(name) {
+                    self.write_output(&format!("{}\n", element));
+                }
+                Value::Null
+            }
+            // join(arr, sep) is the elements in their printed form with sep between them
+            "join" => {
+                check_arity(name, &values, 2);
+                let separator = values[1].expect_str(name);
+                let printed: Vec<String> = values[0]
+                    .expect_array(name)
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect();
+                let joined = printed.join(separator);
+                self.track_allocation(joined.len());
+                Value::Str(joined)
+            }
+            // fill(value, count) makes an array of `count` copies of `value`
+            "fill" => {
+                check_arity(name, &values, 2);
+                let count = values[1].expect_int(name);
+                if count < 0 {
+                    panic!("fill count must not be negative, got {}", count);
+                }
+                // Checked before building the array, so a huge count fails without allocating
+                self.track_allocation(count as usize);
+                Value::Array(vec![values[0].clone(); count as usize])
+            }
+            // pad_left(s, width) / pad_right(s, width) / center(s, width), each with an optional
+            // fill character. Non-strings are padded in their printed form
+            "pad_left" | "pad_right" | "center" => {
+                check_arity_range(name, &values, 2, 3);
+                let text = values[0].to_string();
+                let width = values[1].expect_int(name);
+                let fill = values.get(2).map_or(' ', |v| expect_char(name, v));
+                let missing = (width.max(0) as usize).saturating_sub(text.chars().count());
+                self.track_allocation(text.len().saturating_add(missing));
+                // center puts the odd space on the right
+                let left = match name {
+                    "pad_left" => missing,
+                    "pad_right" => 0,
+                    _ => missing / 2,
+                };
+                let mut padded: String = std::iter::repeat_n(fill, left).collect();
+                padded.push_str(&text);
+                padded.extend(std::iter::repeat_n(fill, missing - left));
+                Value::Str(padded)
+            }
+            // safe_div(a, b, default) is a / b, or default instead of an error when b is zero
+            "safe_div" => {
+                check_arity(name, &values, 3);
+                let default = values[2].clone();
+                match (&values[0], &values[1]) {
+                    (Value::Int(_) | Value::BigInt(_), Value::Int(0)) => default,
+                    // The smallest int divided by -1 overflows, the same as it does with `/`
+                    (
+                        a @ (Value::Int(_) | Value::BigInt(_)),
+                        b @ (Value::Int(_) | Value::BigInt(_)),
+                    ) => self
+                        .int_arithmetic(a, "/", b)
+                        .expect("both operands are ints"),
+                    (Value::Double(_), Value::Double(b)) if *b == 0.0 => default,
+                    (Value::Double(a), Value::Double(b)) => Value::Double(a / b),
+                    (a, b) => panic!(
+                        "safe_div expects two ints or two doubles, got {} {} and {} {}",
+                        a.type_name(),
+                        a,
+                        b.type_name(),
+                        b
+                    ),
+                }
+            }
+            // mod_floor(a, b) is the mathematical modulo: the result takes the sign of b,
+            // so mod_floor(-7, 3) is 2 where -7 % 3 is -1
+            "mod_floor" => {
+                check_arity(name, &values, 2);
+                let (a, b) = (values[0].expect_int(name), values[1].expect_int(name));
+                if b == 0 {
+                    panic!("Modulo by zero");
+                }
+                let r = a.wrapping_rem(b);
+                if r != 0 && (r < 0) != (b < 0) {
+                    Value::Int(r + b)
+                } else {
+                    Value::Int(r)
+                }
+            }
+            // floor_div(a, b) rounds the quotient down, so floor_div(-7, 2) is -4 where
+            // -7 / 2 is -3. It pairs with mod_floor: floor_div(a, b) * b + mod_floor(a, b) == a
+            "floor_div" => {
+                check_arity(name, &values, 2);
+                let (a, b) = (values[0].expect_int(name), values[1].expect_int(name));
+                if b == 0 {
+                    panic!("Division by zero");
+                }
+                let q = a.checked_div(b).unwrap_or_else(|| int_overflow(a, "/", b));
+                if a % b != 0 && (a < 0) != (b < 0) {
+                    Value::Int(q - 1)
+                } else {
+                    Value::Int(q)
+                }
+            }
+            // abs(n) is n without its sign. The smallest int has no positive counterpart, so it's
+            // an overflow error like -n would be
+            "abs" => {
+                check_arity(name, &values, 1);
+                if self.big_ints() {
+                    return int_value(values[0].expect_big_int(name).abs());
+                }
+                let n = values[0].expect_int(name);
+                Value::Int(
+                    n.checked_abs()
+                        .unwrap_or_else(|| int_out_of_range(&format!("abs({})", n))),
+                )
+            }
+            // sign(n) is -1, 0 or 1 as n is negative, zero or positive
+            "sign" => {
+                check_arity(name, &values, 1);
+                if self.big_ints() {
+                    return int_value(values[0].expect_big_int(name).signum());
+                }
+                Value::Int(values[0].expect_int(name).signum())
+            }
+            // gcd(a, b) is the greatest common divisor of |a| and |b|, by Euclid's algorithm.
+            // gcd(n, 0) is |n|, so gcd(0, 0) is 0
+            "gcd" => {
+                check_arity(name, &values, 2);
+                if self.big_ints() {
+                    let (a, b) = (
+                        values[0].expect_big_int(name),
+                        values[1].expect_big_int(name),
+                    );
+                    return int_value(gcd_big_int(a, b));
+                }
+                let (a, b) = (values[0].expect_int(name), values[1].expect_int(name));
+                Value::Int(gcd_int(name, a, b))
+            }
+            // lcm(a, b) is the smallest positive multiple of both, or 0 if either is 0. It's
+            // worked out as |a| / gcd(a, b) * |b|, which only overflows if the answer does
+            "lcm" => {
+                check_arity(name, &values, 2);
+                if self.big_ints() {
+                    let (a, b) = (
+                        values[0].expect_big_int(name),
+                        values[1].expect_big_int(name),
+                    );
+                    if a.is_zero() || b.is_zero() {
+                        return Value::Int(0);
+                    }
+                    return int_value((&a / gcd_big_int(a.clone(), b.clone()) * b).abs());
+                }
+                let (a, b) = (values[0].expect_int(name), values[1].expect_int(name));
+                if a == 0 || b == 0 {
+                    return Value::Int(0);
+                }
+                let lcm = (a / gcd_int(name, a, b))
+                    .checked_mul(b)
+                    .and_then(i64::checked_abs)
+                    .unwrap_or_else(|| int_out_of_range(&format!("lcm({}, {})", a, b)));
+                Value::Int(lcm)
+            }
+            // find(arr, "f") is the first element f returns true for, or null if there's none.
+            // find, any and all stop calling f as soon as the answer is known
+            "find" => {
+                check_arity(name, &values, 2);
+                let function = values[1].expect_str(name);
+                for element in values[0].expect_array(name) {
+                    if self.call_predicate(name, function, element) {
+                        return element.clone();
+                    }
+                }
+                Value::Null
+            }
+            // any(arr, "f") is whether f returns true for at least one element
+            "any" => {
+                check_arity(name, &values, 2);
+                let function = values[1].expect_str(name);
+                let found = values[0]
+                    .expect_array(name)
+                    .iter()
+                    .any(|element| self.call_predicate(name, function, element));
+                Value::Bool(found)
+            }
+            // all(arr, "f") is whether f returns true for every element (so true for [])
+            "all" => {
+                check_arity(name, &values, 2);
+                let function = values[1].expect_str(name);
+                let every = values[0]
+                    .expect_array(name)
+                    .iter()
+                    .all(|element| self.call_predicate(name, function, element));
+                Value::Bool(every)
+            }
+            // json_parse(s) turns JSON text into maps, arrays, ints, doubles, strings, bools
+            // and null
+            "json_parse" => {
+                check_arity(name, &values, 1);
+                json::parse(values[0].expect_str(name))
+            }
+            // json_stringify(x) is x as compact JSON text
+            "json_stringify" => {
+                check_arity(name, &values, 1);
+                Value::Str(json::stringify(&values[0]))
+            }
+            // range(end), range(start, end) or range(start, end, step): the ints from start
+            // (default 0) up to but not including end, as an array. A for-each loop over a
+            // range(...) call counts without building the array
+            "range" => {
+                let (start, end, step) = range_bounds(&values);
+                self.track_allocation(range_len(start, end, step));
+                Value::Array(range_values(start, end, step).map(Value::Int).collect())
+            }
+            // min(a, b, ...) / max(a, b, ...) is the smallest / largest argument, min(arr) the
+            // smallest element, and min(arr, "f") the element f gives the smallest result for.
+            // Ints and doubles compare by value; ties go to the first
+            "min" | "max" => {
+                let wanted = if name == "min" {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                };
+                match &values[..] {
+                    [] => panic!("Function {} expects at least 1 argument, got 0", name),
+                    [Value::Array(items), Value::Str(function)] => {
+                        let mut best: Option<(&Value, Value)> = None;
+                        for item in items {
+                            let key =
+                                self.call_function_by_name(name, function, vec![item.clone()]);
+                            // The first key is compared with itself, which checks it's a number
+                            let best_key = best.as_ref().map_or(&key, |(_, best_key)| best_key);
+                            let better = compare_numbers(name, &key, best_key) == wanted;
+                            if best.is_none() || better {
+                                best = Some((item, key));
+                            }
+                        }
+                        match best {
+                            Some((item, _)) => item.clone(),
+                            None => panic!("{} of an empty array", name),
+                        }
+                    }
+                    [Value::Array(items)] => extreme(name, items, wanted),
+                    _ => extreme(name, &values, wanted),
+                }
+            }
+            // trim(s), trim_start(s) and trim_end(s) remove whitespace from both ends, the
+            // start or the end of s
+            "trim" | "trim_start" | "trim_end" => {
+                check_arity(name, &values, 1);
+                let s = values[0].expect_str(name);
+                let trimmed = match name {
+                    "trim" => s.trim(),
+                    "trim_start" => s.trim_start(),
+                    _ => s.trim_end(),
+                };
+                Value::Str(trimmed.to_string())
+            }
+            // strip_prefix(s, p) and strip_suffix(s, x) remove p from the start or x from the
+            // end of s once, if it's there. Otherwise s comes back unchanged
+            "strip_prefix" | "strip_suffix" => {
+                check_arity(name, &values, 2);
+                let s = values[0].expect_str(name);
+                let affix = values[1].expect_str(name);
+                let stripped = if name == "strip_prefix" {
+                    s.strip_prefix(affix)
+                } else {
+                    s.strip_suffix(affix)
+                };
+                Value::Str(stripped.unwrap_or(s).to_string())
+            }
+            // starts_with(s, p) and ends_with(s, x) say whether s starts with p or ends with x
+            "starts_with" | "ends_with" => {
+                check_arity(name, &values, 2);
+                let s = values[0].expect_str(name);
+                let affix = values[1].expect_str(name);
+                Value::Bool(if name == "starts_with" {
+                    s.starts_with(affix)
+                } else {
+                    s.ends_with(affix)
+                })
+            }
+            // replace(s, from, to) replaces every occurrence of from in s with to, left to right
+            // and without rescanning the replacements
+            "replace" => {
+                check_arity(name, &values, 3);
+                let s = values[0].expect_str(name);
+                let from = values[1].expect_str(name);
+                let to = values[2].expect_str(name);
+                // An empty string occurs everywhere, so there's no sensible answer
+                if from.is_empty() {
+                    panic!("replace can't replace an empty string");
+                }
+                let count = s.matches(from).count();
+                self.track_allocation(s.len() - count * from.len() + count * to.len());
+                Value::Str(s.replace(from, to))
+            }
+            // sum(arr) adds up an array of numbers, with the same overflow check as +. All ints
+            // give an int; any double makes it a sum of doubles. An empty array sums to 0
+            "sum" => {
+                check_arity(name, &values, 1);
+                let Value::Array(elements) = &values[0] else {
+                    values[0].type_error(name, "an array")
+                };
+                let mut total = Value::Int(0);
+                for (i, element) in elements.iter().enumerate() {
+                    total = match (total, element) {
+                        (
+                            t @ (Value::Int(_) | Value::BigInt(_)),
+                            n @ (Value::Int(_) | Value::BigInt(_)),
+                        ) => self.int_arithmetic(&t, "+", n).unwrap(),
+                        (Value::Int(t), Value::Double(d)) => Value::Double(t as f64 + d),
+                        (Value::Double(t), Value::Int(n)) => Value::Double(t + *n as f64),
+                        (Value::Double(t), Value::Double(d)) => Value::Double(t + d),
+                        // A big int total or element only exists in big int mode
+                        (t @ Value::BigInt(_), Value::Double(d)) => {
+                            Value::Double(expect_number(name, &t) + d)
+                        }
+                        (Value::Double(t), n @ Value::BigInt(_)) => {
+                            Value::Double(t + expect_number(name, n))
+                        }
+                        (_, other) => panic!(
+                            "sum expects an array of numbers, but element {} is {} {}",
+                            i,
+                            other.type_name(),
+                            other
+                        ),
+                    };
+                }
+                total
+            }
+            // slice(arr, start, end) and substring(s, start, end) copy out elements (or
+            // characters) start up to but not including end. end defaults to the length, and a
+            // negative start or end counts back from it, so substring("hello", -3, -1) is "ll"
+            "slice" => {
+                check_arity_range(name, &values, 2, 3);
+                let arr = values[0].expect_array(name);
+                let (start, end) = slice_bounds(name, &values[1..], arr.len());
+                self.track_allocation(end - start);
+                Value::Array(arr[start..end].to_vec())
+            }
+            "substring" => {
+                check_arity_range(name, &values, 2, 3);
+                let chars: Vec<char> = values[0].expect_str(name).chars().collect();
+                let (start, end) = slice_bounds(name, &values[1..], chars.len());
+                self.track_allocation(end - start);
+                Value::Str(chars[start..end].iter().collect())
+            }
+            // to_chars(s) splits s into an array of one-character strings, and from_chars(arr)
+            // joins an array of strings back into one. Together they let a string be edited
+            // through index assignment: to_chars, change elements, from_chars
+            "to_chars" => {
+                check_arity(name, &values, 1);
+                let s = values[0].expect_str(name);
+                self.track_allocation(s.chars().count());
+                Value::Array(s.chars().map(|c| Value::Str(c.to_string())).collect())
+            }
+            "from_chars" => {
+                check_arity(name, &values, 1);
+                let mut joined = String::new();
+                for (i, element) in values[0].expect_array(name).iter().enumerate() {
+                    match element {
+                        Value::Str(s) => joined.push_str(s),
+                        other => panic!(
+                            "from_chars expects an array of strings, but element {} is {} {}",
+                            i,
+                            other.type_name(),
+                            other
+                        ),
+                    }
+                }
+                self.track_allocation(joined.len());
+                Value::Str(joined)
+            }
+            // compare(a, b) is -1, 0 or 1 as a comes before, with or after b in the total order
+            // over all values (see compare_values), so any two values can be compared
+            "compare" => {
+                check_arity(name, &values, 2);
+                Value::Int(match compare_values(&values[0], &values[1]) {
+                    Ordering::Less => -1,
+                    Ordering::Equal => 0,
+                    Ordering::Greater => 1,
+                })
+            }
+            // equals(a, b) is the same as a == b
+            "equals" => {
+                check_arity(name, &values, 2);
+                Value::Bool(values_equal(&values[0], &values[1]))
+            }
+            // contains(arr, x) is whether some element == x, index_of(arr, x) is the index of the
+            // first one (or -1) and count(arr, x) how many there are. All three compare like ==,
+            // so nested arrays and maps match by content and 1 matches 1.0
+            "contains" | "index_of" | "count" => {
+                check_arity(name, &values, 2);
+                let items = values[0].expect_array(name);
+                let mut matches = items
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, item)| values_equal(item, &values[1]));
+                match name {
+                    "contains" => Value::Bool(matches.next().is_some()),
+                    "index_of" => Value::Int(matches.next().map_or(-1, |(i, _)| i as i64)),
+                    _ => Value::Int(matches.count() as i64),
+                }
+            }
+            // group_by(arr, "f") maps each result of f to the elements it gave that result for,
+            // in the order they first appear
+            "group_by" => {
+                check_arity(name, &values, 2);
+                let function = values[1].expect_str(name);
+                let mut groups = ValueKeyedMap::default();
+                for element in values[0].expect_array(name) {
+                    let key = self.call_function_by_name(name, function, vec![element.clone()]);
+                    if let Value::Array(group) = groups.entry(name, key, Value::Array(Vec::new())) {
+                        group.push(element.clone());
+                    }
+                }
+                self.track_allocation(values[0].expect_array(name).len());
+                groups.into_map()
+            }
+            // frequencies(arr) maps each element to how many times it appears, in the order
+            // they first appear
+            "frequencies" => {
+                check_arity(name, &values, 1);
+                let mut counts = ValueKeyedMap::default();
+                for element in values[0].expect_array(name) {
+                    if let Value::Int(count) = counts.entry(name, element.clone(), Value::Int(0)) {
+                        *count += 1;
+                    }
+                }
+                self.track_allocation(counts.len());
+                counts.into_map()
+            }
+            // ref(x) makes a shared reference holding a copy of x
+            "ref" => {
+                check_arity(name, &values, 1);
+                Value::Ref(Rc::new(RefCell::new(values[0].clone())))
+            }
+            // int(x) converts a bool (to 1 or 0), a double (dropping any fraction) or a string
+            // of digits to an int
+            "int" => {
+                check_arity(name, &values, 1);
+                match &values[0] {
+                    Value::Int(_) | Value::BigInt(_) => values[0].clone(),
+                    Value::Bool(b) => Value::Int(i64::from(*b)),
+                    // The cast would saturate silently, so anything out of range is an error
+                    Value::Double(d) if d.is_finite() && d.abs() < 9.2e18 => Value::Int(*d as i64),
+                    Value::Double(_) => panic!("int can't convert double {} to an int", values[0]),
+                    Value::Str(s) => s.trim().parse().map(Value::Int).unwrap_or_else(|_| {
+                        panic!("int can't convert the string \"{}\" to an int", s)
+                    }),
+                    other => other.type_error(name, "a bool, double or string"),
+                }
+            }
+            // abs_diff(a, b) is |a - b|. For ints it never overflows partway, so it works right
+            // up to the ends of the int range; only a result above the largest int is an error
+            "abs_diff" => {
+                check_arity(name, &values, 2);
+                match (&values[0], &values[1]) {
+                    (Value::Int(a), Value::Int(b)) => {
+                        let diff = a.abs_diff(*b);
+                        let diff = i64::try_from(diff).unwrap_or_else(|_| {
+                            panic!(
+                                "abs_diff of {} and {} is {}, too large for an int",
+                                a, b, diff
+                            )
+                        });
+                        Value::Int(diff)
+                    }
+                    (a, b) => {
+                        // Mixed ints and doubles compare as doubles, like clamp
+                        let as_double = |v: &Value| match v {
+                            Value::Int(n) => *n as f64,
+                            Value::Double(d) => *d,
+                            _ => panic!("{} expects numeric arguments, got {} and {}", name, a, b),
+                        };
+                        Value::Double((as_double(a) - as_double(b)).abs())
+                    }
+                }
+            }
+            // read_line() is the next line of the program's input, without its line ending, or
+            // null when there's none left
+            "read_line" => {
+                check_arity(name, &values, 0);
+                match self.read_line() {
+                    Some(line) => {
+                        self.track_allocation(line.len());
+                        Value::Str(line)
+                    }
+                    None => Value::Null,
+                }
+            }
+            // read_file(path) is the whole file as a string. Like write_file, it needs file access
+            "read_file" => {
+                check_arity(name, &values, 1);
+                self.check_file_access(name);
+                let path = values[0].expect_str(name);
+                let contents = fs::read_to_string(path)
+                    .unwrap_or_else(|err| panic!("{}", file_error(name, path, &err)));
+                self.track_allocation(contents.len());
+                Value::Str(contents)
+            }
+            // write_file(path, contents) replaces the file with contents in their printed form,
+            // creating it if it doesn't exist
+            "write_file" => {
+                check_arity(name, &values, 2);
+                self.check_file_access(name);
+                let path = values[0].expect_str(name);
+                if let Err(err) = fs::write(path, values[1].to_string()) {
+                    panic!("{}", file_error(name, path, &err));
+                }
+                Value::Null
+            }
+            // color(s, "red") is s in its printed form wrapped in the ANSI codes for that color,
+            // or just s when colors are off, as they are on the server and in piped output
+            "color" => {
+                check_arity(name, &values, 2);
+                let text = values[0].to_string();
+                let color = values[1].expect_str(name);
+                let Some(&(_, code)) = ANSI_COLORS.iter().find(|(c, _)| *c == color) else {
+                    panic!(
+                        "color doesn't know the color \"{}\"{}",
+                        color,
+                        did_you_mean(color, ANSI_COLORS.iter().map(|(c, _)| *c))
+                    );
+                };
+                if !self.colors() {
+                    return Value::Str(text);
+                }
+                self.track_allocation(text.len() + 9);
+                Value::Str(format!("\x1b[{}m{}\x1b[0m", code, text))
+            }
+            // approx_eq(a, b) or approx_eq(a, b, epsilon) is whether two numbers are within
+            // epsilon of each other, scaled up for numbers bigger than 1 in size. Rounding makes
+            // 0.1 + 0.2 == 0.3 false, but approx_eq(0.1 + 0.2, 0.3) is true
+            "approx_eq" => {
+                check_arity_range(name, &values, 2, 3);
+                let a = expect_number(name, &values[0]);
+                let b = expect_number(name, &values[1]);
+                let epsilon = match values.get(2) {
+                    Some(epsilon) => expect_number(name, epsilon),
+                    None => DEFAULT_EPSILON,
+                };
+                if !(epsilon >= 0.0 && epsilon.is_finite()) {
+                    panic!(
+                        "approx_eq epsilon must be a finite number of at least 0, got {}",
+                        epsilon
+                    );
+                }
+                // Equal infinities count, but an infinity is never close to anything else (the
+                // tolerance it would scale to is infinite too)
+                let close = a == b
+                    || (a.is_finite()
+                        && b.is_finite()
+                        && (a - b).abs() <= epsilon * a.abs().max(b.abs()).max(1.0));
+                Value::Bool(close)
+            }
+            _ => panic!("Undefined function: {}", name),
+        }
+    }
+}
+
+impl Interpreter {
+    // Store a mutated array back into the variable it was passed in as, if any
+    fn write_back(&mut self, target: Option<Expr>, value: Value) {
+        if let Some(Expr::Var(name)) = target {
+            self.assign_var(&name, value);
+        }
+    }
+
+    // The pieces of a split string as an array of strings
+    fn split_result(&mut self, s: &str, pieces: Vec<&str>) -> Value {
+        self.track_allocation(s.len() + pieces.len());
+        Value::Array(
+            pieces
+                .into_iter()
+                .map(|p| Value::Str(p.to_string()))
+                .collect(),
+        )
+    }
+
+    // Call a predicate function on one element, checking it answered with a bool
+    fn call_predicate(&mut self, name: &str, function: &str, element: &Value) -> bool {
+        match self.call_function_by_name(name, function, vec![element.clone()]) {
+            Value::Bool(b) => b,
+            other => panic!(
+                "{} predicate '{}' must return a bool, got {} {}",
+                name,
+                function,
+                other.type_name(),
+                other
+            ),
+        }
+    }
+}
+
+// Compile a pattern argument, turning a bad pattern into a runtime error
+fn compile_regex(name: &str, pattern: &Value) -> Regex {
+    let pattern = pattern.expect_str(name);
+    Regex::new(pattern)
+        .unwrap_or_else(|err| panic!("Invalid pattern '{}' passed to {}: {}", pattern, name, err))
+}
+
+// The greatest common divisor of |a| and |b|. Worked out on unsigned numbers so the smallest
+// int doesn't overflow along the way; only a result of 2^63 (from it and 0 or itself) is too big
+fn gcd_int(name: &str, a: i64, b: i64) -> i64 {
+    let (mut x, mut y) = (a.unsigned_abs(), b.unsigned_abs());
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    i64::try_from(x).unwrap_or_else(|_| int_out_of_range(&format!("{}({}, {})", name, a, b)))
+}
+
+// gcd_int for big int mode, where the answer always fits
+fn gcd_big_int(a: BigInt, b: BigInt) -> BigInt {
+    let (mut x, mut y) = (a.abs(), b.abs());
+    while !y.is_zero() {
+        (x, y) = (y.clone(), x % y);
+    }
+    x
+}
+
+// The runtime error for a file read or write that failed, in words rather than an OS code
+fn file_error(name: &str, path: &str, err: &io::Error) -> String {
+    let reason = match err.kind() {
+        io::ErrorKind::NotFound => "there's no such file".to_string(),
+        io::ErrorKind::PermissionDenied => "permission denied".to_string(),
+        io::ErrorKind::InvalidData => "it isn't valid UTF-8 text".to_string(),
+        _ => err.to_string(),
+    };
+    format!("{} failed for \"{}\": {}", name, path, reason)
+}
+
+// The map group_by and frequencies build, keyed by value: values that are == share an entry,
+// so 1 and 1.0 are counted together, and the map key is the first one's printed form
+#[derive(Default)]
+struct ValueKeyedMap {
+    // Each entry's key, the key's printed form, and the entry's value
+    entries: Vec<(Value, String, Value)>,
+}
+
+impl ValueKeyedMap {
+    // The entry for `key`, starting at `initial` if there isn't one yet. Two different values
+    // with the same printed form, like 1 and "1", would be one map key, so that's an error
+    fn entry(&mut self, name: &str, key: Value, initial: Value) -> &mut Value {
+        let key = key.resolve();
+        let index = match self
+            .entries
+            .iter()
+            .position(|(existing, _, _)| values_equal(existing, &key))
+        {
+            Some(index) => index,
+            None => {
+                let text = map_key(name, &key);
+                if let Some((existing, _, _)) = self.entries.iter().find(|(_, k, _)| *k == text) {
+                    panic!(
+                        "{} can't use both {} {} and {} {} as keys: they're different values, but both would be the map key \"{}\"",
+                        name,
+                        existing.type_name(),
+                        existing,
+                        key.type_name(),
+                        key,
+                        text
+                    );
+                }
+                self.entries.push((key, text, initial));
+                self.entries.len() - 1
+            }
+        };
+        &mut self.entries[index].2
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn into_map(self) -> Value {
+        Value::Map(
+            self.entries
+                .into_iter()
+                .map(|(_, text, value)| (text, value))
+                .collect(),
+        )
+    }
+}
+
+// A value used as a map key by group_by and frequencies: a string as it is, and a number or
+// bool in its printed form
+fn map_key(name: &str, key: &Value) -> String {
+    match key {
+        Value::Str(s) => s.clone(),
+        Value::Int(_) | Value::BigInt(_) | Value::Double(_) | Value::Bool(_) => key.to_string(),
+        Value::Ref(shared) => map_key(name, &shared.borrow()),
+        other => panic!(
+            "{} can only use strings, numbers and bools as map keys, got {} {}",
+            name,
+            other.type_name(),
+            other
+        ),
+    }
+}
+
+// The colors color() knows and their ANSI foreground codes
+const ANSI_COLORS: [(&str, u8); 8] = [
+    ("black", 30),
+    ("red", 31),
+    ("green", 32),
+    ("yellow", 33),
+    ("blue", 34),
+    ("magenta", 35),
+    ("cyan", 36),
+    ("white", 37),
+];
+
+// approx_eq's tolerance when none is given. Doubles carry about 16 significant digits, so this
+// allows for a few operations' worth of rounding while still telling apart any numbers a
+// program would mean to be different
+const DEFAULT_EPSILON: f64 = 1e-9;
+
+// A numeric argument as a double, for builtins that only work on doubles
+fn expect_number(name: &str, value: &Value) -> f64 {
+    match value {
+        Value::Int(n) => *n as f64,
+        Value::Double(d) => *d,
+        Value::BigInt(n) => n.to_f64().unwrap_or(f64::NAN),
+        other => panic!(
+            "{} expects numbers, got {} {}",
+            name,
+            other.type_name(),
+            other
+        ),
+    }
+}
+
+// How many pieces a split with this limit argument can make: one more than the number of
+// splits allowed. A limit below 1 would never split, which is almost certainly a mistake
+fn split_limit(name: &str, limit: &Value) -> usize {
+    let limit = limit.expect_int(name);
+    if limit < 1 {
+        panic!("{} limit must be at least 1, got {}", name, limit);
+    }
+    usize::try_from(limit).map_or(usize::MAX, |limit| limit.saturating_add(1))
+}
+
+// The start, end and step of a range(...) call's arguments
+pub(crate) fn range_bounds(values: &[Value]) -> (i64, i64, i64) {
+    check_arity_range("range", values, 1, 3);
+    let ints: Vec<i64> = values.iter().map(|v| v.expect_int("range")).collect();
+    match ints[..] {
+        [end] => (0, end, 1),
+        [start, end] => (start, end, 1),
+        [_, _, 0] => panic!("range step can't be zero"),
+        [start, end, step] => (start, end, step),
+        _ => unreachable!(),
+    }
+}
+
+// The numbers in a range, counting up to end for a positive step or down to it for a
+// negative one (end itself is never included)
+pub(crate) fn range_values(start: i64, end: i64, step: i64) -> impl Iterator<Item = i64> {
+    std::iter::successors(Some(start), move |n| n.checked_add(step)).take_while(move |&n| {
+        if step > 0 {
+            n < end
+        } else {
+            n > end
+        }
+    })
+}
+
+// How many numbers range_values gives, worked out without counting them
+fn range_len(start: i64, end: i64, step: i64) -> usize {
+    let (start, end, step) = (start as i128, end as i128, step as i128);
+    let span = if step > 0 { end - start } else { start - end };
+    if span <= 0 {
+        0
+    } else {
+        ((span - 1) / step.abs() + 1)
+            .try_into()
+            .unwrap_or(usize::MAX)
+    }
+}
+
+// The value that compares as `wanted` (Less for the minimum) against all the others,
+// keeping the first of any ties
+fn extreme(name: &str, values: &[Value], wanted: Ordering) -> Value {
+    let mut best = values
+        .first()
+        .unwrap_or_else(|| panic!("{} of an empty array", name));
+    // The first value is compared with itself, which checks it's a number
+    for value in values {
+        if compare_numbers(name, value, best) == wanted {
+            best = value;
+        }
+    }
+    best.clone()
+}
+
+// Check an argument is a string of exactly one character
+fn expect_char(name: &str, value: &Value) -> char {
+    let s = value.expect_str(name);
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ => panic!("{} fill must be a single character, got \"{}\"", name, s),
+    }
+}
+
+// Check an index argument is an int in 0..limit
+fn expect_index(name: &str, value: &Value, limit: usize) -> usize {
+    match value.expect_int(name) {
+        i if i >= 0 && (i as usize) < limit => i as usize,
+        i => panic!("{} index {} is out of bounds", name, i),
+    }
+}
+
+// The start and (optional) end arguments of slice or substring as a range of 0..=len. A
+// negative index counts back from the end, so -1 is the last position. An index that's still
+// outside the range after that is an error, like a negative index that's too far back
+fn slice_bounds(name: &str, args: &[Value], len: usize) -> (usize, usize) {
+    let position = |value: &Value| {
+        let i = value.expect_int(name);
+        let from_start = if i < 0 { i + len as i64 } else { i };
+        if from_start < 0 || from_start > len as i64 {
+            panic!("{} index {} is out of bounds for length {}", name, i, len);
+        }
+        from_start as usize
+    };
+    let start = position(&args[0]);
+    let end = args.get(1).map_or(len, position);
+    if start > end {
+        panic!(
+            "{} start {} is after end {}",
+            name,
+            args[0],
+            args.get(1).map_or(len.to_string(), Value::to_string)
+        );
+    }
+    (start, end)
+}
+
+// Panic unless the builtin got exactly `expected` arguments
+fn check_arity(name: &str, args: &[Value], expected: usize) {
+    if args.len() != expected {
+        panic!(
+            "Function {} expects {} arguments, got {}",
+            name,
+            expected,
+            args.len()
+        );
+    }
+}
+
+// Order two numbers, promoting an int to a double when the types are mixed
+fn compare_numbers(name: &str, a: &Value, b: &Value) -> Ordering {
+    let ordering = match (a, b) {
+        (Value::Int(l), Value::Int(r)) => Some(l.cmp(r)),
+        (Value::Double(l), Value::Double(r)) => l.partial_cmp(r),
+        (Value::Int(l), Value::Double(r)) => (*l as f64).partial_cmp(r),
+        (Value::Double(l), Value::Int(r)) => l.partial_cmp(&(*r as f64)),
+        (Value::BigInt(_), Value::Int(_) | Value::BigInt(_))
+        | (Value::Int(_), Value::BigInt(_)) => Some(compare_values(a, b)),
+        (Value::BigInt(_), Value::Double(d)) | (Value::Double(d), Value::BigInt(_))
+            if d.is_nan() =>
+        {
+            None
+        }
+        (Value::BigInt(_), Value::Double(_)) | (Value::Double(_), Value::BigInt(_)) => {
+            Some(compare_values(a, b))
+        }
+        _ => panic!("{} expects numeric arguments, got {} and {}", name, a, b),
+    };
+    ordering.unwrap_or_else(|| panic!("{} cannot compare NaN values", name))
+}
+
+// Panic unless the builtin got between `min` and `max` arguments
+fn check_arity_range(name: &str, args: &[Value], min: usize, max: usize) {
+    if args.len() < min || args.len() > max {
+        panic!(
+            "Function {} expects {} to {} arguments, got {}",
+            name,
+            min,
+            max,
+            args.len()
+        );
+    }
+}
+
+// Render a number with a separator between each group of three digits
+fn format_number(value: &Value, separator: &str) -> String {
+    match value {
+        Value::Int(i) => {
+            let sign = if *i < 0 { "-" } else { "" };
+            format!(
+                "{}{}",
+                sign,
+                group_thousands(&i.unsigned_abs().to_string(), separator)
+            )
+        }
+        Value::Double(d) => {
+            let sign = if *d < 0.0 { "-" } else { "" };
+            // The digits are the ones woof prints, so 2.0 keeps its .0. Only the integer part
+            // gets grouped, the fraction is kept as-is, and infinities, NaN and numbers written
+            // with an exponent have no groups to separate
+            let text = format_double(d.abs());
+            match text.split_once('.') {
+                Some((whole, fraction)) if !fraction.contains('e') => {
+                    format!("{}{}.{}", sign, group_thousands(whole, separator), fraction)
+                }
+                _ => format!("{}{}", sign, text),
+            }
+        }
+        Value::BigInt(n) => {
+            let sign = if n.sign() == Sign::Minus { "-" } else { "" };
+            format!(
+                "{}{}",
+                sign,
+                group_thousands(&n.magnitude().to_string(), separator)
+            )
+        }
+        other => other.type_error("format_number", "an int or double"),
+    }
+}
+
+// Append `value` to `out` as JSON-style text, with each nested array or map element on its
+// own line indented two spaces deeper. A ref is laid out like the value it holds. Storing a
+// ref inside itself is an error, so there are no cycles to guard against. Like woof, it stops
+// at MAX_DISPLAY_DEPTH levels of nesting
+fn write_pretty(value: &Value, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth + 1);
+    match value {
+        Value::Ref(shared) => write_pretty(&shared.borrow(), depth, out),
+        Value::Str(s) => out.push_str(&quote_string(s)),
+        Value::Array(arr) if arr.is_empty() => out.push_str("[]"),
+        Value::Map(entries) if entries.is_empty() => out.push_str("{}"),
+        Value::Array(_) if depth == MAX_DISPLAY_DEPTH => out.push_str("[...]"),
+        Value::Map(_) if depth == MAX_DISPLAY_DEPTH => out.push_str("{...}"),
+        Value::Array(arr) => {
+            out.push_str("[\n");
+            for (i, element) in arr.iter().enumerate() {
+                out.push_str(&indent);
+                write_pretty(element, depth + 1, out);
+                out.push_str(if i + 1 < arr.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&"  ".repeat(depth));
+            out.push(']');
+        }
+        Value::Map(entries) => {
+            out.push_str("{\n");
+            for (i, (key, element)) in entries.iter().enumerate() {
+                out.push_str(&indent);
+                out.push_str(&quote_string(key));
+                out.push_str(": ");
+                write_pretty(element, depth + 1, out);
+                out.push_str(if i + 1 < entries.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&"  ".repeat(depth));
+            out.push('}');
+        }
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+// Wrap a string in double quotes, escaping it the way JSON does
+fn quote_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for ch in s.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Insert `separator` every three digits, counting from the right
+fn group_thousands(digits: &str, separator: &str) -> String {
+    let mut grouped = String::new();
+    for (i, ch) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push_str(separator);
+        }
+        grouped.push(ch);
+    }
+    grouped
+}
@@ -1,14 +1,27 @@
 #![allow(dead_code)]
 // Import the standard HashMap type for tracking variable bindings
-use std::collections::HashMap;
+use std::any::Any;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::Arc;
 
 // Import AST node definitions for expressions and statements
-use crate::ast::{Expr, Stmt, Type};
+use num_bigint::BigInt;
+use num_traits::FromPrimitive;
+
+use crate::ast::{Accessor, Expr, LValue, Stmt, Type};
+use crate::builtins::{range_bounds, range_values, BUILTINS};
 
 // Define a function definition structure
 #[derive(Debug, Clone)]
 pub struct FunctionDef {
     pub params: Vec<(String, Type)>,
+    // Trailing rest parameter that receives any extra arguments as an array
+    pub rest: Option<(String, Type)>,
     pub return_type: Option<Type>,
     pub body: Vec<Stmt>,
 }
@@ -21,6 +34,17 @@ pub enum Value {
     Str(String),
     Double(f64),
     Array(Vec<Value>),
+    // String-keyed map that keeps its entries in insertion order, so printing and
+    // iterating it is deterministic
+    Map(Vec<(String, Value)>),
+    // The "nothing" value, e.g. the result of a function that doesn't return anything
+    Null,
+    // A value shared by reference, made with ref(x). Copying it copies the reference, so a
+    // change made through any copy (or a function it was passed to) is seen through all of them
+    Ref(Rc<RefCell<Value>>),
+    // An int too big for 64 bits, only made in big int mode. It's always outside the i64
+    // range: a result that fits goes back to being an Int. To programs it's just an int
+    BigInt(BigInt),
 }
 
 // Define a return control flow exception
@@ -28,6 +52,113 @@ pub enum Value {
 pub enum ControlFlow {
     None,
     Return(Option<Value>),
+    // `return f(...)` inside f itself: the arguments for the next iteration of f's body,
+    // which the call loop runs in place instead of recursing
+    TailCall(Vec<Value>),
+}
+
+impl Value {
+    // The FinnLang name for this value's type, for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "string",
+            Value::Double(_) => "double",
+            Value::Array(_) => "array",
+            Value::Map(_) => "map",
+            Value::Null => "null",
+            Value::Ref(_) => "ref",
+            Value::BigInt(_) => "int",
+        }
+    }
+
+    // The value a ref holds (a copy of it), or the value itself if it isn't a ref. Everything
+    // that uses a value rather than passing it along sees through refs this way
+    pub(crate) fn resolve(self) -> Value {
+        match self {
+            Value::Ref(shared) => shared.borrow().clone().resolve(),
+            value => value,
+        }
+    }
+
+    // Whether a value counts as "true" when used as a default with ??
+    // null, false, 0, 0.0, "", [] and {} are falsy, everything else is truthy
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            Value::Int(i) => *i != 0,
+            Value::Double(d) => *d != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            Value::Array(arr) => !arr.is_empty(),
+            Value::Map(entries) => !entries.is_empty(),
+            Value::Null => false,
+            Value::Ref(shared) => shared.borrow().is_truthy(),
+            // Never 0, which is an Int
+            Value::BigInt(_) => true,
+        }
+    }
+
+    // Type checks for builtins and operators, all failing with the same kind of message,
+    // e.g. "insert expects an array, got int 5". `context` names who is doing the check.
+    // (Worded without "Expected" so the error is reported as a runtime error)
+    pub(crate) fn expect_array(&self, context: &str) -> &[Value] {
+        match self {
+            Value::Array(arr) => arr,
+            other => other.type_error(context, "an array"),
+        }
+    }
+
+    pub(crate) fn expect_map(&self, context: &str) -> &[(String, Value)] {
+        match self {
+            Value::Map(entries) => entries,
+            other => other.type_error(context, "a map"),
+        }
+    }
+
+    pub(crate) fn expect_str(&self, context: &str) -> &str {
+        match self {
+            Value::Str(s) => s,
+            other => other.type_error(context, "a string"),
+        }
+    }
+
+    pub(crate) fn expect_int(&self, context: &str) -> i64 {
+        match self {
+            Value::Int(i) => *i,
+            Value::BigInt(n) => panic!(
+                "{} needs an int between {} and {}, got {}",
+                context,
+                i64::MIN,
+                i64::MAX,
+                n
+            ),
+            other => other.type_error(context, "an int"),
+        }
+    }
+
+    // An int of any size, for the builtins that take big ints in big int mode
+    pub(crate) fn expect_big_int(&self, context: &str) -> BigInt {
+        as_big_int(self).unwrap_or_else(|| self.type_error(context, "an int"))
+    }
+
+    pub(crate) fn expect_bool(&self, context: &str) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            other => other.type_error(context, "a bool"),
+        }
+    }
+
+    // The shared error for the expect_* checks
+    pub(crate) fn type_error(&self, context: &str, expected: &str) -> ! {
+        panic!(
+            "{} expects {}, got {} {}",
+            context,
+            expected,
+            self.type_name(),
+            self
+        );
+    }
 }
 
 // Implement how each value variant should be displayed as a string
@@ -35,215 +166,1608 @@ use std::fmt;
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_nested(f, MAX_DISPLAY_DEPTH)
+    }
+}
+
+// A double as printed: the shortest text that reads back as exactly the same double, so
+// printing a double and pasting it into a program gives the same value. It always looks like
+// a double (2.0, not 2), and very large or small magnitudes use exponent notation (1e20, 1.5e-7)
+// like Python does. NaN and infinities print as NaN, inf and -inf, which can't be read back
+pub fn format_double(d: f64) -> String {
+    let magnitude = d.abs();
+    if !d.is_finite() {
+        d.to_string()
+    } else if magnitude != 0.0 && !(1e-4..1e16).contains(&magnitude) {
+        format!("{:e}", d)
+    } else if d.fract() == 0.0 {
+        format!("{:.1}", d)
+    } else {
+        d.to_string()
+    }
+}
+
+// How many levels of arrays and maps inside each other are printed before the rest is
+// shortened to `[...]` or `{...}`, so printing a very deeply nested value can't overflow the stack
+pub const MAX_DISPLAY_DEPTH: usize = 100;
+
+impl Value {
+    // Write the value as printed, with `depth_left` more levels of nesting shown in full
+    fn write_nested(&self, f: &mut fmt::Formatter<'_>, depth_left: usize) -> fmt::Result {
         match self {
             Value::Int(i) => write!(f, "{}", i),
             Value::Bool(b) => write!(f, "{}", b),
             Value::Str(s) => write!(f, "{}", s),
-            Value::Double(d) => write!(f, "{}", d),
+            Value::Double(d) => write!(f, "{}", format_double(*d)),
+            Value::Array(arr) if arr.is_empty() => write!(f, "[]"),
+            Value::Map(entries) if entries.is_empty() => write!(f, "{{}}"),
+            Value::Array(_) if depth_left == 0 => write!(f, "[...]"),
+            Value::Map(_) if depth_left == 0 => write!(f, "{{...}}"),
             Value::Array(arr) => {
-                let elements: Vec<String> = arr.iter().map(|v| v.to_string()).collect();
-                write!(f, "[{}]", elements.join(", "))
+                write!(f, "[")?;
+                for (i, element) in arr.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.write_nested(f, depth_left - 1)?;
+                }
+                write!(f, "]")
             }
+            Value::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", key)?;
+                    value.write_nested(f, depth_left - 1)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Null => write!(f, "null"),
+            Value::Ref(shared) => shared.borrow().write_nested(f, depth_left),
+            Value::BigInt(n) => write!(f, "{}", n),
         }
     }
 }
 
+// How many elements a single array, or bytes a single string, a program may create, so a huge
+// allocation fails with an error instead of exhausting the server's memory. It's a cap on each
+// value rather than on everything alive at once, so building up a value step by step is fine
+// as long as no one step makes anything bigger than this
+pub const DEFAULT_MEMORY_LIMIT: usize = 100_000_000;
+
+// How many function calls may be in progress at once. Each one recurses through the Rust
+// stack (tail calls excepted), so runaway recursion is stopped with an error well before it
+// could overflow the stack the CLI and server run programs on (RUN_STACK_SIZE in lib.rs)
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+// Called just before each statement runs, with the statement and the scopes it can see
+// (innermost last), e.g. for a debugger or a step-through visualizer
+pub type StatementHook = Box<dyn FnMut(&Stmt, &[HashMap<String, Value>])>;
+
+// A function written in Rust by a program embedding FinnLang, registered with
+// Interpreter::register_builtin. It gets the evaluated arguments, and an Err stops the
+// FinnLang program with that message as a runtime error
+pub type NativeFunction = Rc<dyn Fn(Vec<Value>) -> Result<Value, String>>;
+
 // Define the interpreter struct, which holds the environment for variable storage
 pub struct Interpreter {
-    // Environment mapping variable names to their current values
-    env: HashMap<String, Value>,
+    // Stack of scopes mapping variable names to their current values (innermost last)
+    env: Vec<HashMap<String, Value>>,
     // Function definitions mapping function names to their definitions
     functions: HashMap<String, FunctionDef>,
+    // Functions registered from Rust, which take precedence over every other function
+    natives: HashMap<String, NativeFunction>,
     // Output buffer for collecting all output
     output_buffer: String,
+    // Separate buffer for howl() output, which goes to stderr
+    error_output: String,
+    // REPL mode: echo the value of bare top-level expression statements
+    echo_expressions: bool,
+    // Test mode: failed assertions are recorded instead of stopping the program
+    test_mode: bool,
+    // Debug mode: debug; statements write the variables in scope to the error stream
+    debug_mode: bool,
+    // Big int mode: an int result too big for 64 bits becomes a BigInt instead of an error
+    big_ints: bool,
+    // Strict mode: printing the result of a function with no return type is an error
+    strict: bool,
+    // Implicit declaration mode: assigning to a variable that doesn't exist declares it
+    implicit_declare: bool,
+    // Whether read_file and write_file may touch the file system. Off unless whoever runs the
+    // program turns it on, so code sent to the server can't read or write its files
+    file_access: bool,
+    // Whether color() adds ANSI escape codes. Off unless the output is going to a terminal
+    // that shows them, so the server and piped output get plain text
+    colors: bool,
+    assertions_passed: usize,
+    assertion_failures: Vec<String>,
+    // Name of the function whose body this interpreter is running, for spotting tail calls
+    current_function: Option<String>,
+    // How many try statements we're inside. A tail call there would run its body after the
+    // try had finished, outside its catch and after its finally, so it recurses normally
+    try_depth: usize,
+    // Most elements or bytes one new array or string may have
+    memory_limit: usize,
+    // How many function calls deep this interpreter is running
+    call_depth: usize,
+    // Values of the once declarations that have run, by id. Like the output, it's handed to
+    // each function call and back, so a once in a function runs on the first call only
+    once_values: HashMap<usize, Value>,
+    // Lines of input for read_line() to give out in order. Handed to function calls and back
+    // like the output, so a line read inside a function is gone for the caller too
+    input: VecDeque<String>,
+    // Instrumentation callback, if any. It follows calls into functions, so it sees every
+    // statement the program runs
+    statement_hook: Option<StatementHook>,
+    // Set from another thread to stop the program, e.g. when a server run times out. Loops
+    // and calls check it, so even a program that never ends on its own stops soon after
+    stop_flag: Option<Arc<AtomicBool>>,
+}
+
+// Using null as an operand almost always means a function that doesn't return anything
+// was used in an expression, so say that instead of a generic type error
+fn null_operand(operation: &str) -> ! {
+    panic!(
+        "Cannot use null in {}: did you use the result of a function that doesn't return a value?",
+        operation
+    );
+}
+
+// A bool used as a number, e.g. `true + 1`. Bools don't count as 1 and 0 by themselves, so
+// point at the conversion that does
+fn bool_operand(operation: &str, b: bool) -> ! {
+    panic!(
+        "Cannot use bool {} in {}: bools aren't numbers, wrap it in int() to count it as {}",
+        b,
+        operation,
+        i64::from(b)
+    );
+}
+
+// An int operation whose result is outside the int range. It's an error rather than
+// wrapping around to a wrong answer
+pub(crate) fn int_overflow(left: i64, operator: &str, right: i64) -> ! {
+    int_out_of_range(&format!("{} {} {}", left, operator, right))
+}
+
+// The error for an int result, written out as `expression`, that's outside the int range
+pub(crate) fn int_out_of_range(expression: &str) -> ! {
+    panic!(
+        "Integer overflow: {} doesn't fit in an int (the range is {} to {})",
+        expression,
+        i64::MIN,
+        i64::MAX
+    );
+}
+
+// An Int or BigInt as a BigInt, or None for any other value
+fn as_big_int(value: &Value) -> Option<BigInt> {
+    match value {
+        Value::Int(i) => Some(BigInt::from(*i)),
+        Value::BigInt(n) => Some(n.clone()),
+        _ => None,
+    }
+}
+
+// A whole number as a value: an Int if it fits, otherwise a BigInt
+pub(crate) fn int_value(n: BigInt) -> Value {
+    i64::try_from(&n).map_or(Value::BigInt(n), Value::Int)
+}
+
+// One of the int operators + - * / % on BigInts. `/` and `%` truncate towards zero like they
+// do on Ints, and the divisor has already been checked
+fn big_int_arithmetic(left: BigInt, operator: &str, right: BigInt) -> Value {
+    int_value(match operator {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" => left / right,
+        "%" => left % right,
+        _ => unreachable!("not an int operator: {}", operator),
+    })
+}
+
+// Order a BigInt and a double exactly, like compare_int_double
+fn compare_big_int_double(n: &BigInt, d: f64) -> Ordering {
+    match BigInt::from_f64(d.trunc()) {
+        // n is a whole number, so if it matches d's whole part the fraction decides
+        Some(whole) => n
+            .cmp(&whole)
+            .then_with(|| 0.0.partial_cmp(&d.fract()).unwrap_or(Ordering::Equal)),
+        // Infinities and NaNs: NaN and +inf sort above every number, like compare_doubles
+        None if d.is_nan() || d.is_sign_positive() => Ordering::Less,
+        None => Ordering::Greater,
+    }
+}
+
+// Operands of types an arithmetic operator has no meaning for
+fn unsupported_operands(operation: &str, left: &Value, right: &Value) -> ! {
+    panic!(
+        "Unsupported {} types: {} and {}",
+        operation,
+        left.type_name(),
+        right.type_name()
+    );
+}
+
+// <, >, <= and >= on two ints or two doubles. `holds` says which orderings make it true; a
+// NaN isn't ordered against anything, so every comparison with one is false
+fn ordered(operation: &str, left: Value, right: Value, holds: fn(Ordering) -> bool) -> bool {
+    match (left, right) {
+        (Value::Int(l), Value::Int(r)) => holds(l.cmp(&r)),
+        (Value::Double(l), Value::Double(r)) => l.partial_cmp(&r).is_some_and(holds),
+        (Value::Null, _) | (_, Value::Null) => null_operand(operation),
+        (l, r) => match (as_big_int(&l), as_big_int(&r)) {
+            (Some(l), Some(r)) => holds(l.cmp(&r)),
+            _ => panic!("Unsupported types for {}", operation),
+        },
+    }
+}
+
+// Split a comparison into its operator and operands, so a failed assert can show both sides.
+// Anything else comes back unchanged
+fn split_comparison(expr: Expr) -> Result<(&'static str, Expr, Expr), Expr> {
+    match expr {
+        Expr::Eq(left, right) => Ok(("==", *left, *right)),
+        Expr::Neq(left, right) => Ok(("!=", *left, *right)),
+        Expr::LessThan(left, right) => Ok(("<", *left, *right)),
+        Expr::GreaterThan(left, right) => Ok((">", *left, *right)),
+        Expr::LessEqual(left, right) => Ok(("<=", *left, *right)),
+        Expr::GreaterEqual(left, right) => Ok((">=", *left, *right)),
+        other => Err(other),
+    }
+}
+
+// An already evaluated index as an expression that gives it again, for the ones a literal
+// can hold
+fn literal(value: &Value) -> Option<Expr> {
+    match value {
+        Value::Int(n) => Some(Expr::Number(*n)),
+        Value::Str(s) => Some(Expr::StrLiteral(s.clone())),
+        Value::Double(d) => Some(Expr::Double(*d)),
+        Value::Bool(b) => Some(Expr::Bool(*b)),
+        Value::Null => Some(Expr::Null),
+        _ => None,
+    }
+}
+
+// A compound assignment's operation with its left operand (the target) replaced
+fn with_left_operand(operation: Expr, left: Expr) -> Expr {
+    let left = Box::new(left);
+    match operation {
+        Expr::Add(_, right) => Expr::Add(left, right),
+        Expr::Sub(_, right) => Expr::Sub(left, right),
+        Expr::Mul(_, right) => Expr::Mul(left, right),
+        Expr::Div(_, right) => Expr::Div(left, right),
+        Expr::Mod(_, right) => Expr::Mod(left, right),
+        other => other,
+    }
+}
+
+// Apply a comparison operator from split_comparison to two values
+fn compare_with(operator: &str, left: Value, right: Value) -> bool {
+    match operator {
+        "==" => values_equal(&left, &right),
+        "!=" => !values_equal(&left, &right),
+        "<" => ordered("LessThan comparison", left, right, Ordering::is_lt),
+        ">" => ordered("GreaterThan comparison", left, right, Ordering::is_gt),
+        "<=" => ordered("LessEqual comparison", left, right, Ordering::is_le),
+        ">=" => ordered("GreaterEqual comparison", left, right, Ordering::is_ge),
+        _ => unreachable!("not a comparison operator: {}", operator),
+    }
+}
+
+// What `==` and `!=` mean. Same as the derived equality except that an int and a double
+// holding the same number are equal, at any depth, so `1 == 1.0` and `[1] == [1.0]`.
+// The comparison is exact: a double only equals an int it represents without rounding.
+// Everything that compares values for equality (assert_eq, equals, contains, index_of, count)
+// goes through here, so they can't disagree with `==`
+pub(crate) fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Int(i), Value::Double(d)) | (Value::Double(d), Value::Int(i)) => {
+            // 2^63 is the first double past the end of the int range
+            d.fract() == 0.0 && *d >= i64::MIN as f64 && *d < -(i64::MIN as f64) && *d as i64 == *i
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(x, y)| values_equal(x, y))
+        }
+        (Value::Map(a), Value::Map(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|((ka, va), (kb, vb))| ka == kb && values_equal(va, vb))
+        }
+        (Value::BigInt(n), Value::Double(d)) | (Value::Double(d), Value::BigInt(n)) => {
+            compare_big_int_double(n, *d).is_eq()
+        }
+        // A ref compares by what it holds
+        (Value::Ref(a), b) => values_equal(&a.borrow(), b),
+        (a, Value::Ref(b)) => values_equal(a, &b.borrow()),
+        _ => a == b,
+    }
+}
+
+// Every variable visible from the innermost of `scopes`, sorted by name. A name declared in
+// more than one scope gives the innermost one
+pub(crate) fn visible_variables(scopes: &[HashMap<String, Value>]) -> Vec<(&str, &Value)> {
+    let mut variables = BTreeMap::new();
+    for scope in scopes {
+        for (name, value) in scope {
+            variables.insert(name.as_str(), value);
+        }
+    }
+    variables.into_iter().collect()
+}
+
+// The total order behind compare(): null < bools < numbers < strings < arrays < maps, with
+// false < true, ints and doubles ordered by value together, strings by character, and arrays
+// and maps element by element (then shorter first). It agrees with values_equal: two values
+// compare as Equal exactly when they're ==, so -0.0 and 0 are Equal. The one exception is NaN,
+// which isn't == to anything: it sorts after every other number and is Equal to itself
+pub(crate) fn compare_values(a: &Value, b: &Value) -> Ordering {
+    // Position of each type in the order. Ints and doubles share one
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Int(_) | Value::Double(_) | Value::BigInt(_) => 2,
+            Value::Str(_) => 3,
+            Value::Array(_) => 4,
+            Value::Map(_) => 5,
+            Value::Ref(shared) => rank(&shared.borrow()),
+        }
+    }
+    match (a, b) {
+        (Value::Ref(a), b) => compare_values(&a.borrow(), b),
+        (a, Value::Ref(b)) => compare_values(a, &b.borrow()),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Int(a), Value::Int(b)) => a.cmp(b),
+        (Value::Double(a), Value::Double(b)) => compare_doubles(*a, *b),
+        (Value::Int(i), Value::Double(d)) => compare_int_double(*i, *d),
+        (Value::Double(d), Value::Int(i)) => compare_int_double(*i, *d).reverse(),
+        (Value::BigInt(n), Value::Double(d)) => compare_big_int_double(n, *d),
+        (Value::Double(d), Value::BigInt(n)) => compare_big_int_double(n, *d).reverse(),
+        (Value::BigInt(_), _) | (_, Value::BigInt(_)) if rank(a) == rank(b) => {
+            as_big_int(a).cmp(&as_big_int(b))
+        }
+        (Value::Str(a), Value::Str(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => a
+            .iter()
+            .zip(b)
+            .map(|(x, y)| compare_values(x, y))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (Value::Map(a), Value::Map(b)) => a
+            .iter()
+            .zip(b)
+            .map(|((ka, va), (kb, vb))| ka.cmp(kb).then_with(|| compare_values(va, vb)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+// Order two doubles by value, with NaN after every number
+fn compare_doubles(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b)
+        .unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+// Order an int and a double exactly, even where the int has no exact double
+fn compare_int_double(i: i64, d: f64) -> Ordering {
+    match compare_doubles(i as f64, d) {
+        // Rounding can only make them look equal, and then d is a whole number. Below 2^63 it
+        // converts to an int exactly; 2^63 itself is bigger than every int
+        Ordering::Equal if d < -(i64::MIN as f64) => i.cmp(&(d as i64)),
+        Ordering::Equal => Ordering::Less,
+        ordering => ordering,
+    }
+}
+
+// How many differences a failed assert_eq lists before summing up the rest
+const MAX_DIFF_LINES: usize = 10;
+
+// The places where two arrays or maps differ, one line each, e.g. "at [2]: expected 5, got 7".
+// Paths are written the way they'd be read in code: [1].name["two words"]
+fn value_diff(expected: &Value, actual: &Value) -> Vec<String> {
+    let mut lines = Vec::new();
+    collect_diff(String::new(), expected, actual, &mut lines);
+    if lines.len() > MAX_DIFF_LINES {
+        let more = lines.len() - MAX_DIFF_LINES;
+        lines.truncate(MAX_DIFF_LINES);
+        lines.push(format!("... and {} more", more));
+    }
+    lines
+}
+
+fn collect_diff(path: String, expected: &Value, actual: &Value, lines: &mut Vec<String>) {
+    let at = |path: &str| {
+        if path.is_empty() {
+            "at the top".to_string()
+        } else {
+            format!("at {}", path)
+        }
+    };
+    match (expected, actual) {
+        (Value::Array(expected_items), Value::Array(actual_items)) => {
+            for (i, (e, a)) in expected_items.iter().zip(actual_items).enumerate() {
+                collect_diff(format!("{}[{}]", path, i), e, a, lines);
+            }
+            for (i, item) in expected_items.iter().enumerate().skip(actual_items.len()) {
+                lines.push(format!(
+                    "{}: missing, expected {}",
+                    at(&format!("{}[{}]", path, i)),
+                    item
+                ));
+            }
+            for (i, item) in actual_items.iter().enumerate().skip(expected_items.len()) {
+                lines.push(format!(
+                    "{}: unexpected extra element {}",
+                    at(&format!("{}[{}]", path, i)),
+                    item
+                ));
+            }
+        }
+        (Value::Map(expected_entries), Value::Map(actual_entries)) => {
+            let before = lines.len();
+            for (key, expected_value) in expected_entries {
+                let key_path = format!("{}{}", path, key_accessor(key));
+                match actual_entries.iter().find(|(k, _)| k == key) {
+                    Some((_, actual_value)) => {
+                        collect_diff(key_path, expected_value, actual_value, lines)
+                    }
+                    None => lines.push(format!(
+                        "{}: missing, expected {}",
+                        at(&key_path),
+                        expected_value
+                    )),
+                }
+            }
+            for (key, actual_value) in actual_entries {
+                if !expected_entries.iter().any(|(k, _)| k == key) {
+                    let key_path = format!("{}{}", path, key_accessor(key));
+                    lines.push(format!(
+                        "{}: unexpected extra key with {}",
+                        at(&key_path),
+                        actual_value
+                    ));
+                }
+            }
+            // Same entries, so the only difference left is their order
+            if lines.len() == before && !values_equal(expected, actual) {
+                lines.push(format!("{}: same entries in a different order", at(&path)));
+            }
+        }
+        _ if values_equal(expected, actual) => {}
+        _ => lines.push(format!(
+            "{}: expected {}, got {}",
+            at(&path),
+            expected,
+            actual
+        )),
+    }
+}
+
+// How a map key is written after a value: .name for a plain name, ["two words"] otherwise
+fn key_accessor(key: &str) -> String {
+    let mut chars = key.chars();
+    let is_name = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_name {
+        format!(".{}", key)
+    } else {
+        format!("[{:?}]", key)
+    }
+}
+
+// Whether a value fits a declared type. Every element of an array has to fit the element type,
+// so an empty array fits any array type
+fn has_type(value: &Value, ty: &Type) -> bool {
+    match (value, ty) {
+        (Value::Int(_), Type::Int)
+        | (Value::Bool(_), Type::Bool)
+        | (Value::Str(_), Type::String)
+        | (Value::Double(_), Type::Double) => true,
+        (Value::Array(elements), Type::Array(element_type)) => elements
+            .iter()
+            .all(|element| has_type(element, element_type)),
+        (Value::Ref(_), Type::Ref) => true,
+        (Value::BigInt(_), Type::Int) => true,
+        _ => false,
+    }
+}
+
+// A `return` in a function with a declared return type must give a value of that type.
+// A bare `return;` gives nothing, which never fits
+fn check_return_type(function: &str, return_type: &Type, value: Option<&Value>) {
+    match value {
+        Some(value) if has_type(value, return_type) => {}
+        Some(value) => panic!(
+            "Function {} must return {}, but returned {} {}",
+            function,
+            return_type,
+            value.type_name(),
+            value
+        ),
+        None => panic!(
+            "Function {} must return {}, but returned nothing",
+            function, return_type
+        ),
+    }
+}
+
+// Set a map entry, updating it in place if the key exists (so it keeps its position)
+// and adding it at the end otherwise
+pub(crate) fn map_insert(entries: &mut Vec<(String, Value)>, key: String, value: Value) {
+    match entries.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, slot)) => *slot = value,
+        None => entries.push((key, value)),
+    }
+}
+
+// Look up a map entry by key, erroring on non-string or missing keys
+fn map_get(entries: &[(String, Value)], key: &Value) -> Value {
+    let Value::Str(key) = key else {
+        panic!("Map keys must be strings, got {} {}", key.type_name(), key);
+    };
+    entries
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value.clone())
+        .unwrap_or_else(|| panic!("Key '{}' not found in map", key))
+}
+
+// The element or entry that `key` refers to inside an array or map, for walking down to the
+// place an assignment writes to. Every step on the way has to exist already
+fn child_mut<'a>(container: &'a mut Value, key: &Value) -> &'a mut Value {
+    match container {
+        Value::Array(items) => {
+            let i = array_index(key, items.len());
+            &mut items[i]
+        }
+        Value::Map(entries) => {
+            let Value::Str(key) = key else {
+                panic!("Map keys must be strings, got {} {}", key.type_name(), key);
+            };
+            match entries.iter_mut().find(|(k, _)| k == key) {
+                Some((_, value)) => value,
+                None => panic!("Key '{}' not found in map", key),
+            }
+        }
+        other => panic!("Cannot index into a non-array value: {}", other),
+    }
+}
+
+// Store `value` at `key` in an array or map: replacing an existing element, or replacing or
+// adding a map entry
+fn store(container: &mut Value, key: Value, value: Value) {
+    match container {
+        Value::Array(items) => {
+            let i = array_index(&key, items.len());
+            items[i] = value;
+        }
+        Value::Map(entries) => match key {
+            Value::Str(key) => map_insert(entries, key, value),
+            key => panic!("Map keys must be strings, got {} {}", key.type_name(), key),
+        },
+        other => panic!("Cannot index into a non-array value: {}", other),
+    }
+}
+
+// Assign `value` to the place `keys` leads to inside `slot`, or to `slot` itself if there are
+// no keys. A ref on the way is written through, so the change shows through every copy of it
+fn assign_into(slot: &mut Value, mut keys: Vec<Value>, value: Value) {
+    if let Value::Ref(shared) = slot {
+        let shared = Rc::clone(shared);
+        if holds_ref(&value, &shared) {
+            panic!("Cannot store a ref inside itself");
+        }
+        assign_into(&mut shared.borrow_mut(), keys, value);
+        return;
+    }
+    if keys.len() <= 1 {
+        match keys.pop() {
+            None => *slot = value,
+            Some(key) => store(slot, key, value),
+        }
+        return;
+    }
+    let key = keys.remove(0);
+    assign_into(child_mut(slot, &key), keys, value);
+}
+
+// Whether `value` is or contains `target`, which storing it inside `target` would turn into
+// a cycle. A ref that's already borrowed is one being assigned through, so it counts too
+fn holds_ref(value: &Value, target: &Rc<RefCell<Value>>) -> bool {
+    match value {
+        Value::Ref(shared) => {
+            Rc::ptr_eq(shared, target)
+                || shared
+                    .try_borrow()
+                    .map_or(true, |inner| holds_ref(&inner, target))
+        }
+        Value::Array(items) => items.iter().any(|item| holds_ref(item, target)),
+        Value::Map(entries) => entries.iter().any(|(_, value)| holds_ref(value, target)),
+        _ => false,
+    }
+}
+
+// A runtime error caught with catch_unwind
+pub(crate) type PanicPayload = Box<dyn Any + Send>;
+
+// The message a runtime error (a panic) was raised with
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Unknown compilation/runtime error occurred".to_string()
+    }
+}
+
+// Check an array index is an int within 0..len, so every bad index gets the same errors
+fn array_index(index: &Value, len: usize) -> usize {
+    match index {
+        Value::Int(i) if *i >= 0 && (*i as usize) < len => *i as usize,
+        Value::Int(i) => panic!(
+            "Index {} is out of bounds for an array of length {}",
+            i, len
+        ),
+        other => panic!(
+            "Array index must be an integer, got {} {}",
+            other.type_name(),
+            other
+        ),
+    }
+}
+
+// Number of single-character edits needed to turn one name into another. Swapping two
+// neighbouring characters counts as one edit, since it's as common a typo as a wrong letter
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // The rows for the first i - 1 and i characters of a, as the loop reaches each i
+    let mut before: Vec<usize> = Vec::new();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == cb { 0 } else { 1 };
+            let mut distance = (prev[j] + cost).min(prev[j + 1] + 1).min(row[j] + 1);
+            if i > 0 && j > 0 && *ca == b[j - 1] && a[i - 1] == *cb {
+                distance = distance.min(before[j - 1] + 1);
+            }
+            row.push(distance);
+        }
+        before = std::mem::replace(&mut prev, row);
+    }
+    prev[b.len()]
+}
+
+// Suffix for "undefined" errors naming the closest known name, if any is close enough
+// to be a likely typo (ties go to the alphabetically first name so errors are stable)
+pub(crate) fn did_you_mean<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> String {
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .filter(|c| *c != name)
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= limit)
+        .min()
+        .map(|(_, c)| format!(", did you mean '{}'?", c))
+        .unwrap_or_default()
+}
+
+// Call a function registered with register_builtin, turning an Err into a runtime error
+fn call_native(name: &str, native: &NativeFunction, arg_values: Vec<Value>) -> Value {
+    native(arg_values).unwrap_or_else(|message| panic!("{}: {}", name, message))
 }
 
 impl Interpreter {
     // Create a new interpreter with an empty environment
     pub fn new() -> Self {
         Interpreter {
-            env: HashMap::new(),
+            env: vec![HashMap::new()],
             functions: HashMap::new(),
+            natives: HashMap::new(),
             output_buffer: String::new(),
+            error_output: String::new(),
+            echo_expressions: false,
+            test_mode: false,
+            debug_mode: false,
+            big_ints: false,
+            strict: false,
+            implicit_declare: false,
+            file_access: false,
+            colors: false,
+            assertions_passed: 0,
+            assertion_failures: Vec::new(),
+            current_function: None,
+            try_depth: 0,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            call_depth: 0,
+            once_values: HashMap::new(),
+            input: VecDeque::new(),
+            statement_hook: None,
+            stop_flag: None,
+        }
+    }
+
+    // Turn test mode on or off. In test mode a failed assertion is counted and the
+    // program carries on, rather than stopping with an error
+    pub fn set_test_mode(&mut self, test_mode: bool) {
+        self.test_mode = test_mode;
+    }
+
+    // Make a Rust function callable from FinnLang as `name(...)`. It's looked up before
+    // builtins and functions defined with funct, and a program that defines a function with
+    // the same name stops with an error rather than quietly replacing it
+    pub fn register_builtin(
+        &mut self,
+        name: &str,
+        function: impl Fn(Vec<Value>) -> Result<Value, String> + 'static,
+    ) {
+        self.natives.insert(name.to_string(), Rc::new(function));
+    }
+
+    // Whether `name` was registered with register_builtin
+    pub(crate) fn has_native(&self, name: &str) -> bool {
+        self.natives.contains_key(name)
+    }
+
+    // Call `hook` before every statement from now on, including those in function bodies
+    pub fn set_statement_hook(&mut self, hook: StatementHook) {
+        self.statement_hook = Some(hook);
+    }
+
+    // Stop the program with a runtime error once `flag` is set. It's checked before each pass
+    // through a loop and each function call, and a stopped program can't catch the error
+    pub fn set_stop_flag(&mut self, flag: Arc<AtomicBool>) {
+        self.stop_flag = Some(flag);
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stop_flag
+            .as_ref()
+            .is_some_and(|flag| flag.load(AtomicOrdering::Relaxed))
+    }
+
+    // Stop here if the stop flag has been set
+    fn check_stopped(&self) {
+        if self.is_stopped() {
+            panic!("Code execution was stopped");
         }
     }
 
+    // + - * / or % when both operands are ints, or None to leave other operand types to the
+    // operator. Dividing by zero is an error, and so is a result too big for an int, unless
+    // big int mode keeps it as a BigInt
+    pub(crate) fn int_arithmetic(
+        &self,
+        left: &Value,
+        operator: &str,
+        right: &Value,
+    ) -> Option<Value> {
+        match (left, right) {
+            (Value::Int(_) | Value::BigInt(_), Value::Int(0)) if operator == "/" => {
+                panic!("Division by zero")
+            }
+            (Value::Int(_) | Value::BigInt(_), Value::Int(0)) if operator == "%" => {
+                panic!("Modulo by zero")
+            }
+            (Value::Int(l), Value::Int(r)) => {
+                let (l, r) = (*l, *r);
+                let result = match operator {
+                    "+" => l.checked_add(r),
+                    "-" => l.checked_sub(r),
+                    "*" => l.checked_mul(r),
+                    // The one overflowing case is the smallest int divided by -1
+                    "/" => l.checked_div(r),
+                    // Only the smallest int % -1 can overflow, and its remainder is 0
+                    "%" => Some(l.wrapping_rem(r)),
+                    _ => unreachable!("not an int operator: {}", operator),
+                };
+                Some(match result {
+                    Some(n) => Value::Int(n),
+                    None if self.big_ints => {
+                        big_int_arithmetic(BigInt::from(l), operator, BigInt::from(r))
+                    }
+                    None => int_overflow(l, operator, r),
+                })
+            }
+            _ => {
+                let (l, r) = (as_big_int(left)?, as_big_int(right)?);
+                Some(big_int_arithmetic(l, operator, r))
+            }
+        }
+    }
+
+    // Turn big int mode on or off. In it, ints have no size limit: a result too big for 64 bits
+    // is kept exactly instead of being an overflow error. Results that fit stay plain i64s, so
+    // ordinary arithmetic costs the same either way
+    pub fn set_big_ints(&mut self, big_ints: bool) {
+        self.big_ints = big_ints;
+    }
+
+    pub(crate) fn big_ints(&self) -> bool {
+        self.big_ints
+    }
+
+    // Turn debug mode on or off. Outside it, debug; statements do nothing
+    pub fn set_debug_mode(&mut self, debug_mode: bool) {
+        self.debug_mode = debug_mode;
+    }
+
+    // Turn strict mode on or off. In it, `woof(f())` or `howl(f())` where f has no return type
+    // is an error: f's result is always null, so it was most likely meant to be called on its own
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    // Turn implicit declaration mode on or off. In it, `x = 5;` with no x in scope declares x
+    // in the current scope, as if it were `let x = 5;`. Outside it, that's an error
+    pub fn set_implicit_declare(&mut self, implicit_declare: bool) {
+        self.implicit_declare = implicit_declare;
+    }
+
+    // Allow or forbid read_file and write_file. Only turn this on for code that's as trusted
+    // as whoever runs it, like a file run from the command line
+    pub fn set_file_access(&mut self, file_access: bool) {
+        self.file_access = file_access;
+    }
+
+    // Turn color() output on or off. With it off, color() gives back its text unchanged
+    pub fn set_colors(&mut self, colors: bool) {
+        self.colors = colors;
+    }
+
+    pub(crate) fn colors(&self) -> bool {
+        self.colors
+    }
+
+    // Stop a builtin that touches the file system when file access is off
+    pub(crate) fn check_file_access(&self, name: &str) {
+        if !self.file_access {
+            panic!(
+                "{} isn't available here: this program was run without file access",
+                name
+            );
+        }
+    }
+
+    // In strict mode, stop before printing the result of a call to a function with no return
+    // type. Only a direct call is checked; a call inside a bigger expression is left alone
+    fn check_printable(&self, statement: &str, expr: &Expr) {
+        if !self.strict {
+            return;
+        }
+        if let Expr::FunctionCall(name, _) = expr {
+            if let Some(FunctionDef {
+                return_type: None, ..
+            }) = self.functions.get(name)
+            {
+                panic!(
+                    "Cannot {} the result of {}(): it has no return type, so it never gives a value. Call it on its own as `{}();` instead",
+                    statement, name, name
+                );
+            }
+        }
+    }
+
+    // Change how many elements or bytes a single array or string the program creates may have
+    pub fn set_memory_limit(&mut self, limit: usize) {
+        self.memory_limit = limit;
+    }
+
+    // Add text to the program's output exactly as given, e.g. for print_with
+    pub(crate) fn write_output(&mut self, text: &str) {
+        self.output_buffer.push_str(text);
+    }
+
+    // Check a new array of `elements` elements, or string of that many bytes, against the
+    // memory limit. Call this before building the value, so an oversized request fails
+    // without allocating anything
+    pub(crate) fn track_allocation(&mut self, elements: usize) {
+        if elements > self.memory_limit {
+            panic!(
+                "Memory limit exceeded: an array or string would have more than {} elements or bytes",
+                self.memory_limit
+            );
+        }
+    }
+
+    // Number of assertions that passed, and the messages of the ones that failed
+    pub fn assertion_results(&self) -> (usize, &[String]) {
+        (self.assertions_passed, &self.assertion_failures)
+    }
+
+    // Count an assertion in test mode, or stop with its message if it failed otherwise
+    fn record_assertion(&mut self, passed: bool, failure: impl FnOnce() -> String) {
+        if passed {
+            self.assertions_passed += 1;
+        } else if self.test_mode {
+            self.assertion_failures.push(failure());
+        } else {
+            panic!("{}", failure());
+        }
+    }
+
+    // Evaluate an assertion's optional message into a ": message" suffix
+    fn assertion_message(&mut self, message: Option<Expr>) -> String {
+        message
+            .map(|expr| format!(": {}", self.eval(expr)))
+            .unwrap_or_default()
+    }
+
+    // Get back to the top level after a runtime error escaped run, as the REPL does before
+    // its next input. The error can leave the scopes of the loops and blocks it was inside
+    // behind, along with whatever the failed statement printed, which would otherwise turn up
+    // in the next run's output
+    pub fn recover_from_error(&mut self) {
+        self.env.truncate(1);
+        self.output_buffer.clear();
+        self.error_output.clear();
+    }
+
+    // Take everything written with howl() so far
+    pub fn take_error_output(&mut self) -> String {
+        let mut error_output = std::mem::take(&mut self.error_output);
+        // Match run(), which trims the trailing newline from normal output
+        if error_output.ends_with('\n') {
+            error_output.pop();
+        }
+        error_output
+    }
+
+    // Give the program lines of input to read with read_line(), replacing any it hasn't read.
+    // There's no other input: once these run out, read_line() gives null
+    pub fn set_input(&mut self, lines: Vec<String>) {
+        self.input = lines.into();
+    }
+
+    // The next line of input, if there's any left
+    pub(crate) fn read_line(&mut self) -> Option<String> {
+        self.input.pop_front()
+    }
+
+    // Turn REPL-style echoing of top-level expression values on or off
+    pub fn set_echo_expressions(&mut self, echo: bool) {
+        self.echo_expressions = echo;
+    }
+
     // Execute a program (a vector of statements) in order
     pub fn run(&mut self, program: Vec<Stmt>) -> String {
         let mut output = String::new();
         // Execute each statement in sequence
         for stmt in program {
-            match self.execute_with_control(stmt) {
-                (Some(result), ControlFlow::None) => {
-                    output.push_str(&result);
-                    output.push('\n');
-                }
-                (Some(result), ControlFlow::Return(_)) => {
-                    output.push_str(&result);
-                    output.push('\n');
+            if !self.run_top_level(stmt, &mut output) {
+                break;
+            }
+        }
+        output.trim_end().to_string() // remove trailing newline
+    }
+
+    // Like run, but a runtime error stops the program without losing what it printed so far.
+    // Returns the output and, if a statement failed, the error and the line that statement
+    // starts on
+    pub fn run_best_effort(
+        &mut self,
+        program: Vec<(usize, Stmt)>,
+    ) -> (String, Option<(PanicPayload, usize)>) {
+        let mut output = String::new();
+        let mut failure = None;
+        for (line, stmt) in program {
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| self.run_top_level(stmt, &mut output)));
+            match result {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(payload) => {
+                    // Output from calls the failing statement made still happened
+                    output.push_str(&std::mem::take(&mut self.output_buffer));
+                    failure = Some((payload, line));
                     break;
                 }
-                (None, ControlFlow::Return(_)) => {
-                    break;
+            }
+        }
+        (output.trim_end().to_string(), failure)
+    }
+
+    // Run one top-level statement, appending what it prints to `output`.
+    // Returns false if the program should stop (a top-level return)
+    fn run_top_level(&mut self, stmt: Stmt, output: &mut String) -> bool {
+        // In REPL mode a bare expression like `x + 1;` shows its value
+        if let (true, Stmt::ExprStmt(expr)) = (self.echo_expressions, &stmt) {
+            let value = self.eval(expr.clone());
+            // Side-effect output (e.g. from function calls) comes before the value
+            output.push_str(&self.output_buffer);
+            self.output_buffer.clear();
+            // Calling a function that returns nothing shouldn't echo "null"
+            if value != Value::Null {
+                output.push_str(&value.to_string());
+                output.push('\n');
+            }
+            return true;
+        }
+        let control = self.execute_with_control(stmt);
+        output.push_str(&self.output_buffer);
+        self.output_buffer.clear();
+        matches!(control, ControlFlow::None)
+    }
+
+    // Look up a variable, searching from the innermost scope outward
+    fn get_var(&self, name: &str) -> Option<&Value> {
+        self.env.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    // Get a mutable reference to a variable, searching from the innermost scope outward
+    fn get_var_mut(&mut self, name: &str) -> Option<&mut Value> {
+        self.env
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+
+    // Declare a variable in the innermost scope
+    fn define_var(&mut self, name: String, value: Value) {
+        self.env.last_mut().unwrap().insert(name, value);
+    }
+
+    // Update an existing variable in the nearest scope that declares it
+    pub(crate) fn assign_var(&mut self, name: &str, value: Value) -> bool {
+        match self.get_var_mut(name) {
+            Some(slot) => {
+                assign_into(slot, Vec::new(), value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // "did you mean" suffix for an unknown variable, checked against every visible scope
+    fn suggest_variable(&self, name: &str) -> String {
+        did_you_mean(
+            name,
+            self.env
+                .iter()
+                .flat_map(|scope| scope.keys().map(String::as_str)),
+        )
+    }
+
+    // Check a call's argument count and bind the arguments as the function's parameters,
+    // packing everything past the fixed parameters into the rest parameter
+    fn bind_arguments(&mut self, name: &str, func_def: &FunctionDef, mut arg_values: Vec<Value>) {
+        let fixed = func_def.params.len();
+        if func_def.rest.is_some() && arg_values.len() < fixed {
+            panic!(
+                "Function {} expects at least {} arguments, got {}",
+                name,
+                fixed,
+                arg_values.len()
+            );
+        } else if func_def.rest.is_none() && arg_values.len() != fixed {
+            panic!(
+                "Function {} expects {} arguments, got {}",
+                name,
+                fixed,
+                arg_values.len()
+            );
+        }
+        let extra = arg_values.split_off(fixed);
+        for ((param_name, _param_type), arg_value) in func_def.params.iter().zip(arg_values) {
+            self.define_var(param_name.clone(), arg_value);
+        }
+        if let Some((rest_name, _rest_type)) = &func_def.rest {
+            self.define_var(rest_name.clone(), Value::Array(extra));
+        }
+    }
+
+    // Assign to a variable or to a place inside one (`p.items[0].name = x`). The indices are
+    // evaluated left to right before the value, then the variable is changed in place
+    fn assign_place(&mut self, target: LValue, expr: Expr) {
+        let keys = self.eval_keys(target.path);
+        let value = self.eval_shared(expr);
+        self.store_place(target.name, keys, value);
+    }
+
+    // The keys a place's path leads through: each index evaluated, left to right, and each
+    // field as a string
+    fn eval_keys(&mut self, path: Vec<Accessor>) -> Vec<Value> {
+        path.into_iter()
+            .map(|accessor| match accessor {
+                Accessor::Index(index) => self.eval(index),
+                Accessor::Field(field) => Value::Str(field),
+            })
+            .collect()
+    }
+
+    // Store an evaluated value at the place `keys` lead to inside the variable `name`
+    fn store_place(&mut self, name: String, keys: Vec<Value>, value: Value) {
+        if self.get_var(&name).is_none() {
+            // Only a whole variable can be declared this way: `x[0] = 1` has nothing to index
+            if self.implicit_declare && keys.is_empty() {
+                self.define_var(name, value);
+                return;
+            }
+            panic!(
+                "Cannot assign to undeclared variable: {}{}",
+                name,
+                self.suggest_variable(&name)
+            );
+        }
+        let slot = self.get_var_mut(&name).unwrap();
+        assign_into(slot, keys, value);
+    }
+
+    // Call a user-defined function with already evaluated arguments. It runs in an
+    // interpreter of its own that only shares functions, output and the memory limit
+    pub(crate) fn call_user_function(
+        &mut self,
+        name: &str,
+        func_def: &FunctionDef,
+        arg_values: Vec<Value>,
+    ) -> Value {
+        if self.call_depth >= MAX_CALL_DEPTH {
+            panic!(
+                "Too much recursion: calling {} would make more than {} calls in progress at once",
+                name, MAX_CALL_DEPTH
+            );
+        }
+        let mut func_interpreter = Interpreter::new();
+        func_interpreter.call_depth = self.call_depth + 1;
+        func_interpreter.functions = self.functions.clone();
+        func_interpreter.natives = self.natives.clone();
+        func_interpreter.test_mode = self.test_mode;
+        func_interpreter.debug_mode = self.debug_mode;
+        func_interpreter.big_ints = self.big_ints;
+        func_interpreter.strict = self.strict;
+        func_interpreter.implicit_declare = self.implicit_declare;
+        func_interpreter.file_access = self.file_access;
+        func_interpreter.colors = self.colors;
+        func_interpreter.current_function = Some(name.to_string());
+        func_interpreter.memory_limit = self.memory_limit;
+        // The function prints straight onto the end of the caller's output, so
+        // everything stays in the order it ran
+        func_interpreter.output_buffer = std::mem::take(&mut self.output_buffer);
+        func_interpreter.error_output = std::mem::take(&mut self.error_output);
+        func_interpreter.statement_hook = self.statement_hook.take();
+        func_interpreter.stop_flag = self.stop_flag.clone();
+        func_interpreter.once_values = std::mem::take(&mut self.once_values);
+        func_interpreter.input = std::mem::take(&mut self.input);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            func_interpreter.run_function_body(name, func_def, arg_values)
+        }));
+
+        // Hand the output back even if the body failed, so a try around the call
+        // (or a best-effort run) keeps what was printed before the error
+        self.output_buffer = std::mem::take(&mut func_interpreter.output_buffer);
+        self.error_output = std::mem::take(&mut func_interpreter.error_output);
+        self.statement_hook = func_interpreter.statement_hook.take();
+        self.once_values = std::mem::take(&mut func_interpreter.once_values);
+        self.input = std::mem::take(&mut func_interpreter.input);
+        // Assertions made inside the function count towards the caller's results
+        self.assertions_passed += func_interpreter.assertions_passed;
+        self.assertion_failures
+            .append(&mut func_interpreter.assertion_failures);
+        match result {
+            // Falling off the end (or a bare `return;`) gives null
+            Ok(return_value) => return_value.unwrap_or(Value::Null),
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    // Names of the functions defined with funct and registered from Rust, for suggestions
+    fn function_names(&self) -> impl Iterator<Item = &str> {
+        self.functions
+            .keys()
+            .chain(self.natives.keys())
+            .map(String::as_str)
+    }
+
+    // Call the user-defined (or registered) function named by a builtin's argument, e.g. the
+    // "is_even" in any(nums, "is_even")
+    pub(crate) fn call_function_by_name(
+        &mut self,
+        context: &str,
+        name: &str,
+        arg_values: Vec<Value>,
+    ) -> Value {
+        if let Some(native) = self.natives.get(name).cloned() {
+            return call_native(name, &native, arg_values);
+        }
+        match self.functions.get(name).cloned() {
+            Some(func_def) => self.call_user_function(name, &func_def, arg_values),
+            None => {
+                let known = self.function_names();
+                panic!(
+                    "{} expects the name of a function defined with funct, got \"{}\"{}",
+                    context,
+                    name,
+                    did_you_mean(name, known)
+                );
+            }
+        }
+    }
+
+    // Run a function's body with the given arguments, returning what it returned. Each pass of
+    // the loop runs the body once; a tail call starts another pass with fresh parameters rather
+    // than a nested call
+    fn run_function_body(
+        &mut self,
+        name: &str,
+        func_def: &FunctionDef,
+        mut arg_values: Vec<Value>,
+    ) -> Option<Value> {
+        'call: loop {
+            self.check_stopped();
+            self.env = vec![HashMap::new()];
+            self.bind_arguments(name, func_def, arg_values);
+            for stmt in func_def.body.clone() {
+                match self.execute_with_control(stmt) {
+                    ControlFlow::None => {}
+                    // Every return, however deeply nested in ifs and loops, ends up here, so
+                    // this is the one place returned values are checked against the return type
+                    ControlFlow::Return(value) => {
+                        if let Some(return_type) = &func_def.return_type {
+                            check_return_type(name, return_type, value.as_ref());
+                        }
+                        return value;
+                    }
+                    ControlFlow::TailCall(next_args) => {
+                        arg_values = next_args;
+                        continue 'call;
+                    }
                 }
-                _ => {}
             }
-            // Also include any output collected during expression evaluation (like function calls)
-            if !self.output_buffer.is_empty() {
-                output.push_str(&self.output_buffer);
-                self.output_buffer.clear();
+            // Reaching the end of the body gives nothing, which a declared return type rules out
+            if let Some(return_type) = &func_def.return_type {
+                panic!(
+                    "Function {} must return {}, but reached the end of its body without returning",
+                    name, return_type
+                );
             }
+            return None;
         }
-        output.trim_end().to_string() // remove trailing newline
     }
 
-    // Execute a single statement, returning output and control flow
-    fn execute_with_control(&mut self, stmt: Stmt) -> (Option<String>, ControlFlow) {
+    // Evaluate builtin arguments left to right, expanding any ...spread. Refs are seen through
+    pub(crate) fn eval_list(&mut self, exprs: Vec<Expr>) -> Vec<Value> {
+        self.eval_list_shared(exprs)
+            .into_iter()
+            .map(Value::resolve)
+            .collect()
+    }
+
+    // Evaluate user function arguments or array elements left to right, expanding any
+    // ...spread. Refs stay refs, so the function or array shares them
+    fn eval_list_shared(&mut self, exprs: Vec<Expr>) -> Vec<Value> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            match expr {
+                Expr::Spread(inner) => match self.eval(*inner) {
+                    Value::Array(items) => values.extend(items),
+                    other => panic!(
+                        "Cannot spread {} {}: only arrays can be spread",
+                        other.type_name(),
+                        other
+                    ),
+                },
+                expr => values.push(self.eval_shared(expr)),
+            }
+        }
+        values
+    }
+
+    // Execute a list of statements, stopping early on return
+    fn execute_block(&mut self, stmts: Vec<Stmt>) -> ControlFlow {
+        for stmt in stmts {
+            let control = self.execute_with_control(stmt);
+            if !matches!(control, ControlFlow::None) {
+                return control;
+            }
+        }
+        ControlFlow::None
+    }
+
+    // Run one pass of a loop body in a scope of its own, so every iteration starts with fresh
+    // variables: a `let` in the body declares a new variable each time round rather than
+    // updating the previous iteration's, and none of them are visible after the loop
+    fn execute_iteration(&mut self, body: Vec<Stmt>) -> ControlFlow {
+        self.check_stopped();
+        self.env.push(HashMap::new());
+        let control = self.execute_block(body);
+        self.env.pop();
+        control
+    }
+
+    // Run a try/catch/finally block in its own scope (with an optional variable bound in it),
+    // catching any runtime error. Anything printed before the error stays printed
+    fn execute_guarded(
+        &mut self,
+        stmts: Vec<Stmt>,
+        binding: Option<(String, Value)>,
+    ) -> Result<ControlFlow, PanicPayload> {
+        let depth = self.env.len();
+        self.env.push(HashMap::new());
+        if let Some((name, value)) = binding {
+            self.define_var(name, value);
+        }
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.execute_block(stmts)));
+        // An error can leave the scopes of nested blocks behind, so drop back to where we were
+        self.env.truncate(depth);
+        result
+    }
+
+    // Execute a single statement. Anything it prints is appended to the output buffer as it
+    // happens, so output always comes out in the order the program ran
+    fn execute_with_control(&mut self, stmt: Stmt) -> ControlFlow {
+        if let Some(hook) = self.statement_hook.as_mut() {
+            hook(&stmt, &self.env);
+        }
         match stmt {
-            Stmt::Let(_var_type_opt, name, expr) => {
-                let value = self.eval(expr);
-                self.env.insert(name, value);
-                (None, ControlFlow::None)
+            Stmt::Let(_var_type_opt, name, expr, _) => {
+                let value = self.eval_shared(expr);
+                self.define_var(name, value);
+                ControlFlow::None
             }
 
-            Stmt::Print(expr) => {
-                let value = self.eval(expr);
-                (Some(value.to_string()), ControlFlow::None)
+            // If the initializer fails, nothing is remembered and the next time tries again
+            Stmt::Once(id, _var_type_opt, name, expr, _) => {
+                let value = match self.once_values.get(&id) {
+                    Some(value) => value.clone(),
+                    None => {
+                        let value = self.eval_shared(expr);
+                        self.once_values.insert(id, value.clone());
+                        value
+                    }
+                };
+                self.define_var(name, value);
+                ControlFlow::None
             }
 
-            Stmt::While(cond, body) => {
-                let mut output = String::new();
-                loop {
-                    if let Value::Bool(true) = self.eval(cond.clone()) {
-                        for stmt in &body {
-                            let (out, control) = self.execute_with_control(stmt.clone());
-                            if let Some(out) = out {
-                                output.push_str(&out);
-                                output.push('\n');
-                            }
-                            if let ControlFlow::Return(val) = control {
-                                return (
-                                    if output.is_empty() {
-                                        None
-                                    } else {
-                                        Some(output)
-                                    },
-                                    ControlFlow::Return(val),
-                                );
-                            }
+            Stmt::LetGroup(lets) => {
+                for stmt in lets {
+                    self.execute_with_control(stmt);
+                }
+                ControlFlow::None
+            }
+
+            Stmt::LetDestructure(names, expr, _) => {
+                match self.eval(expr) {
+                    Value::Array(values) => {
+                        if values.len() != names.len() {
+                            panic!(
+                                "Cannot destructure an array of length {} into {} variables",
+                                values.len(),
+                                names.len()
+                            );
+                        }
+                        for (name, value) in names.into_iter().zip(values) {
+                            self.define_var(name, value);
                         }
-                    } else {
-                        break;
                     }
+                    other => panic!("Cannot destructure a non-array value: {}", other),
                 }
-                if output.is_empty() {
-                    (None, ControlFlow::None)
+                ControlFlow::None
+            }
+
+            Stmt::Print(expr) => {
+                self.check_printable("woof", &expr);
+                // Evaluated first, so output from calls in the expression comes before the value
+                let value = self.eval(expr);
+                self.output_buffer.push_str(&value.to_string());
+                self.output_buffer.push('\n');
+                ControlFlow::None
+            }
+
+            Stmt::Assert(condition, message) => {
+                // A comparison has its operands evaluated here (once each, left to right) so a
+                // failure can show the values, e.g. `Assertion failed: 3 > 5`
+                let (passed, compared) = match split_comparison(condition) {
+                    Ok((operator, left, right)) => {
+                        let l = self.eval(left);
+                        let r = self.eval(right);
+                        let compared = format!("{} {} {}", l, operator, r);
+                        (compare_with(operator, l, r), Some(compared))
+                    }
+                    Err(condition) => (self.eval(condition).expect_bool("assert"), None),
+                };
+                let message = if passed {
+                    String::new()
                 } else {
-                    (Some(output), ControlFlow::None)
+                    self.assertion_message(message)
+                };
+                self.record_assertion(passed, || match (message.is_empty(), compared) {
+                    (true, Some(compared)) => format!("Assertion failed: {}", compared),
+                    (true, None) => "Assertion failed: condition is false".to_string(),
+                    (false, Some(compared)) => {
+                        format!("Assertion failed{} ({})", message, compared)
+                    }
+                    (false, None) => format!("Assertion failed{}", message),
+                });
+                ControlFlow::None
+            }
+
+            Stmt::AssertEq(actual, expected, message) => {
+                let actual = self.eval(actual);
+                let expected = self.eval(expected);
+                let passed = values_equal(&actual, &expected);
+                let message = if passed {
+                    String::new()
+                } else {
+                    self.assertion_message(message)
+                };
+                self.record_assertion(passed, || {
+                    let mut failure = format!(
+                        "Assertion failed{}: expected {}, got {}",
+                        message, expected, actual
+                    );
+                    // For arrays and maps, also point at exactly where they differ
+                    if let (Value::Array(_), Value::Array(_)) | (Value::Map(_), Value::Map(_)) =
+                        (&expected, &actual)
+                    {
+                        for line in value_diff(&expected, &actual) {
+                            failure.push_str("\n  ");
+                            failure.push_str(&line);
+                        }
+                    }
+                    failure
+                });
+                ControlFlow::None
+            }
+
+            Stmt::PrintErr(expr) => {
+                self.check_printable("howl", &expr);
+                let value = self.eval(expr);
+                self.error_output.push_str(&value.to_string());
+                self.error_output.push('\n');
+                ControlFlow::None
+            }
+
+            Stmt::While(cond, body) => {
+                while let Value::Bool(true) = self.eval(cond.clone()) {
+                    let control = self.execute_iteration(body.clone());
+                    if !matches!(control, ControlFlow::None) {
+                        return control;
+                    }
                 }
+                ControlFlow::None
             }
 
             Stmt::If(cond, if_block, elifs, else_block) => {
-                let mut output = String::new();
                 if let Value::Bool(true) = self.eval(cond) {
-                    for stmt in if_block {
-                        let (out, control) = self.execute_with_control(stmt);
-                        if let Some(out) = out {
-                            output.push_str(&out);
-                            output.push('\n');
+                    return self.execute_block(if_block);
+                }
+                // Check elif branches
+                for (elif_cond, elif_block) in elifs {
+                    if let Value::Bool(true) = self.eval(elif_cond) {
+                        return self.execute_block(elif_block);
+                    }
+                }
+                match else_block {
+                    Some(else_block) => self.execute_block(else_block),
+                    None => ControlFlow::None,
+                }
+            }
+
+            // Evaluate the indices once, then read the place through the values they gave and
+            // write the result back to it
+            Stmt::CompoundAssign(target, expr) if !target.path.is_empty() => {
+                let keys = self.eval_keys(target.path.clone());
+                let mut place = Expr::Var(target.name.clone());
+                for (accessor, key) in target.path.into_iter().zip(&keys) {
+                    place = match accessor {
+                        Accessor::Index(index) => {
+                            let index = literal(key).unwrap_or(index);
+                            Expr::Index(Box::new(place), Box::new(index))
                         }
-                        if let ControlFlow::Return(val) = control {
-                            return (
-                                if output.is_empty() {
-                                    None
-                                } else {
-                                    Some(output)
-                                },
-                                ControlFlow::Return(val),
-                            );
+                        Accessor::Field(field) => Expr::Field(Box::new(place), field),
+                    };
+                }
+                let value = self.eval_shared(with_left_operand(expr, place));
+                self.store_place(target.name, keys, value);
+                ControlFlow::None
+            }
+
+            // A compound assignment to a plain variable has no indices to run twice
+            Stmt::Assign(target, expr) | Stmt::CompoundAssign(target, expr) => {
+                // Fast path: `s = s + x` on a string appends in place instead of copying s
+                if let (true, Expr::Add(left, right)) = (target.path.is_empty(), &expr) {
+                    let name = &target.name;
+                    let is_self_append = matches!(&**left, Expr::Var(var) if var == name)
+                        && matches!(self.get_var(name), Some(Value::Str(_)));
+                    if is_self_append {
+                        let suffix = self.eval((**right).clone()).to_string();
+                        let current_len = match self.get_var(name) {
+                            Some(Value::Str(s)) => s.len(),
+                            _ => 0,
+                        };
+                        self.track_allocation(current_len.saturating_add(suffix.len()));
+                        if let Some(Value::Str(s)) = self.get_var_mut(name) {
+                            s.push_str(&suffix);
                         }
+                        return ControlFlow::None;
                     }
-                    return (
-                        if output.is_empty() {
-                            None
-                        } else {
-                            Some(output)
-                        },
-                        ControlFlow::None,
-                    );
-                } else {
-                    // Check elif branches
-                    for (elif_cond, elif_block) in elifs {
-                        if let Value::Bool(true) = self.eval(elif_cond) {
-                            for stmt in elif_block {
-                                let (out, control) = self.execute_with_control(stmt);
-                                if let Some(out) = out {
-                                    output.push_str(&out);
-                                    output.push('\n');
-                                }
-                                if let ControlFlow::Return(val) = control {
-                                    return (
-                                        if output.is_empty() {
-                                            None
-                                        } else {
-                                            Some(output)
-                                        },
-                                        ControlFlow::Return(val),
-                                    );
-                                }
-                            }
-                            return (
-                                if output.is_empty() {
-                                    None
-                                } else {
-                                    Some(output)
-                                },
-                                ControlFlow::None,
-                            );
+                }
+
+                self.assign_place(target, expr);
+                ControlFlow::None
+            }
+
+            Stmt::ForEach(first, second, subject, body) => {
+                // (index, element) or (key, value) pairs, taken up front so changing the subject
+                // in the body doesn't change what's iterated
+                let (is_map, items): (bool, Box<dyn Iterator<Item = (Value, Value)>>) =
+                    match subject {
+                        // Looping straight over the range builtin counts lazily instead of
+                        // building the whole array first, so `for (i in range(10000000))` is cheap
+                        Expr::FunctionCall(name, args)
+                            if name == "range" && !self.functions.contains_key(&name) =>
+                        {
+                            let (start, end, step) = range_bounds(&self.eval_list(args));
+                            let numbers = range_values(start, end, step)
+                                .enumerate()
+                                .map(|(i, n)| (Value::Int(i as i64), Value::Int(n)));
+                            (false, Box::new(numbers))
                         }
-                    }
-                    if let Some(else_block) = else_block {
-                        for stmt in else_block {
-                            let (out, control) = self.execute_with_control(stmt);
-                            if let Some(out) = out {
-                                output.push_str(&out);
-                                output.push('\n');
+                        subject => match self.eval(subject) {
+                            Value::Array(items) => {
+                                let items = items
+                                    .into_iter()
+                                    .enumerate()
+                                    .map(|(i, item)| (Value::Int(i as i64), item));
+                                (false, Box::new(items))
                             }
-                            if let ControlFlow::Return(val) = control {
-                                return (
-                                    if output.is_empty() {
-                                        None
-                                    } else {
-                                        Some(output)
-                                    },
-                                    ControlFlow::Return(val),
-                                );
+                            Value::Map(entries) => {
+                                let entries = entries
+                                    .into_iter()
+                                    .map(|(key, value)| (Value::Str(key), value));
+                                (true, Box::new(entries))
+                            }
+                            // A string gives its characters as one-character strings, whole
+                            // characters rather than bytes, the same as to_chars
+                            Value::Str(s) => {
+                                let chars: Vec<char> = s.chars().collect();
+                                let chars = chars.into_iter().enumerate().map(|(i, c)| {
+                                    (Value::Int(i as i64), Value::Str(c.to_string()))
+                                });
+                                (false, Box::new(chars))
+                            }
+                            other => {
+                                other.type_error("for-each loop", "an array, a map or a string")
                             }
-                        }
-                    }
-                    (
-                        if output.is_empty() {
-                            None
-                        } else {
-                            Some(output)
                         },
-                        ControlFlow::None,
-                    )
-                }
-            }
+                    };
 
-            Stmt::Assign(name, expr) => {
-                let value = self.eval(expr);
-                if self.env.contains_key(&name) {
-                    self.env.insert(name, value);
-                } else {
-                    panic!("Cannot assign to undeclared variable: {}", name);
+                for (key, value) in items {
+                    self.check_stopped();
+                    // Each pass gets its own scope for the loop variables and the body's lets
+                    self.env.push(HashMap::new());
+                    match &second {
+                        Some(second) => {
+                            self.define_var(first.clone(), key);
+                            self.define_var(second.clone(), value);
+                        }
+                        // With one variable, arrays give their elements and maps their keys
+                        None if is_map => self.define_var(first.clone(), key),
+                        None => self.define_var(first.clone(), value),
+                    }
+                    let control = self.execute_block(body.clone());
+                    self.env.pop();
+                    if !matches!(control, ControlFlow::None) {
+                        return control;
+                    }
                 }
-                (None, ControlFlow::None)
+                ControlFlow::None
             }
 
             Stmt::For(init, condition, update, body) => {
-                let mut output = String::new();
                 // Execute init statement if present
                 if let Some(init_stmt) = init {
                     self.execute_with_control(*init_stmt);
@@ -260,141 +1784,230 @@ impl Interpreter {
                         break;
                     }
                     // Execute body
-                    for stmt in &body {
-                        let (out, control) = self.execute_with_control(stmt.clone());
-                        if let Some(out) = out {
-                            output.push_str(&out);
-                            output.push('\n');
-                        }
-                        if let ControlFlow::Return(val) = control {
-                            return (
-                                if output.is_empty() {
-                                    None
-                                } else {
-                                    Some(output)
-                                },
-                                ControlFlow::Return(val),
-                            );
-                        }
+                    let control = self.execute_iteration(body.clone());
+                    if !matches!(control, ControlFlow::None) {
+                        return control;
                     }
                     // Execute update statement if present
                     if let Some(update_stmt) = &update {
                         self.execute_with_control(*update_stmt.clone());
                     }
                 }
-                if output.is_empty() {
-                    (None, ControlFlow::None)
-                } else {
-                    (Some(output), ControlFlow::None)
-                }
+                ControlFlow::None
             }
 
-            Stmt::FunctionDef(name, params, return_type, body) => {
+            Stmt::FunctionDef(name, params, rest, return_type, body) => {
+                if self.natives.contains_key(&name) {
+                    panic!(
+                        "Cannot define function {}: the program running this code already provides one with that name",
+                        name
+                    );
+                }
                 let func_def = FunctionDef {
                     params,
+                    rest,
                     return_type,
                     body,
                 };
                 self.functions.insert(name, func_def);
-                (None, ControlFlow::None)
+                ControlFlow::None
+            }
+
+            Stmt::Return(Some(Expr::FunctionCall(name, args)))
+                if self.try_depth == 0 && self.current_function.as_ref() == Some(&name) =>
+            {
+                // A function returning a call to itself doesn't need a new frame: hand the
+                // arguments back to the call loop, which reruns the body with them
+                let arg_values = self.eval_list_shared(args);
+                ControlFlow::TailCall(arg_values)
             }
 
             Stmt::Return(expr_opt) => {
-                let value = expr_opt.map(|expr| self.eval(expr));
-                (None, ControlFlow::Return(value))
+                let value = expr_opt.map(|expr| self.eval_shared(expr));
+                ControlFlow::Return(value)
             }
 
             Stmt::ExprStmt(expr) => {
                 // Execute expression for side effects (like function calls)
                 self.eval(expr);
-                // Check if any output was collected during expression evaluation
-                if !self.output_buffer.is_empty() {
-                    let output = self.output_buffer.clone();
-                    self.output_buffer.clear();
-                    (Some(output.trim_end().to_string()), ControlFlow::None)
-                } else {
-                    (None, ControlFlow::None)
+                ControlFlow::None
+            }
+
+            Stmt::Block(body) => {
+                // Run the block in its own scope so declarations don't leak out
+                self.env.push(HashMap::new());
+                let result = self.execute_block(body);
+                self.env.pop();
+                result
+            }
+
+            // Goes to the error stream, like howl, so it never mixes into the program's output
+            Stmt::Debug => {
+                if self.debug_mode {
+                    let variables: Vec<String> = visible_variables(&self.env)
+                        .into_iter()
+                        .map(|(name, value)| format!("{} = {}", name, value))
+                        .collect();
+                    let dump = if variables.is_empty() {
+                        "no variables".to_string()
+                    } else {
+                        variables.join(", ")
+                    };
+                    self.error_output.push_str(&format!("debug: {}\n", dump));
+                }
+                ControlFlow::None
+            }
+
+            Stmt::TypeMatch(subject, cases, default) => {
+                let type_name = self.eval(subject).type_name();
+                let matched = cases
+                    .into_iter()
+                    .find(|(case, _)| case == type_name)
+                    .map(|(_, body)| body)
+                    .or(default);
+                match matched {
+                    Some(body) => self.execute_block(body),
+                    None => ControlFlow::None,
+                }
+            }
+
+            Stmt::Try(body, catch, finally) => {
+                self.try_depth += 1;
+                let mut result = self.execute_guarded(body, None);
+
+                // A caught error runs the catch block with the error message bound to its
+                // variable. Being stopped isn't an error the program gets to handle
+                match (&result, catch) {
+                    (Err(payload), Some((error_name, handler))) if !self.is_stopped() => {
+                        let message = Value::Str(panic_message(payload.as_ref()));
+                        result = self.execute_guarded(handler, Some((error_name, message)));
+                    }
+                    _ => {}
+                }
+
+                // finally always runs, even when the try or catch returned or raised an error.
+                // A return or error inside finally replaces whatever was pending
+                if let Some(cleanup) = finally {
+                    let cleanup_result = self.execute_guarded(cleanup, None);
+                    if !matches!(cleanup_result, Ok(ControlFlow::None)) {
+                        result = cleanup_result;
+                    }
+                }
+                self.try_depth -= 1;
+
+                match result {
+                    Ok(control) => control,
+                    // Nothing handled the error, so keep raising it
+                    Err(payload) => panic::resume_unwind(payload),
                 }
             }
         }
     }
 
-    // Evaluate an expression and return its runtime value and any output from side effects
-    fn eval(&mut self, expr: Expr) -> Value {
+    // Evaluate an expression and return its runtime value and any output from side effects.
+    // A ref is seen through: the result is a copy of what it holds
+    pub(crate) fn eval(&mut self, expr: Expr) -> Value {
+        self.eval_shared(expr).resolve()
+    }
+
+    // Like eval, but a ref comes back as the ref itself. Used where a value is passed along
+    // rather than used (let, assignment, arguments, elements, return), so it stays shared
+    fn eval_shared(&mut self, expr: Expr) -> Value {
         match expr {
             // Literal values
             Expr::Number(n) => Value::Int(n),
             Expr::Bool(b) => Value::Bool(b),
             Expr::StrLiteral(s) => Value::Str(s),
             Expr::Double(f) => Value::Double(f),
+            Expr::Null => Value::Null,
 
             // Lookup a variable’s value in the environment
-            Expr::Var(name) => self
-                .env
-                .get(&name)
-                .cloned()
-                .unwrap_or_else(|| panic!("Undefined variable: {}", name)),
+            Expr::Var(name) => self.get_var(&name).cloned().unwrap_or_else(|| {
+                panic!(
+                    "Undefined variable: {}{}",
+                    name,
+                    self.suggest_variable(&name)
+                )
+            }),
 
             Expr::ArrayLiteral(elements) => {
-                let mut result = Vec::new();
-                for e in elements {
-                    result.push(self.eval(e));
-                }
-                Value::Array(result)
+                let values = self.eval_list_shared(elements);
+                self.track_allocation(values.len());
+                Value::Array(values)
             }
 
-            Expr::Index(array_expr, index_expr) => {
-                let array = self.eval(*array_expr);
-                let index = self.eval(*index_expr);
-                match (array, index) {
-                    (Value::Array(arr), Value::Int(i)) => arr
-                        .get(i as usize)
-                        .cloned()
-                        .unwrap_or(Value::Str("Index out of bounds".into())),
-                    _ => panic!("Invalid indexing operation"),
+            // Entries are evaluated in order; a repeated key keeps its first position
+            Expr::MapLiteral(pairs) => {
+                let mut entries = Vec::with_capacity(pairs.len());
+                for (key, expr) in pairs {
+                    let value = self.eval_shared(expr);
+                    map_insert(&mut entries, key, value);
                 }
+                Value::Map(entries)
             }
 
-            Expr::AssignIndex(array_expr, index_expr, value_expr) => {
-                let array_val = self.eval(*array_expr);
+            Expr::Spread(_) => {
+                panic!("Spread (...) can only be used in function arguments or array literals")
+            }
+
+            Expr::Index(array_expr, index_expr) => {
+                let array = self.eval(*array_expr);
                 let index = self.eval(*index_expr);
-                let new_val = self.eval(*value_expr);
-
-                if let (Value::Array(mut arr), Value::Int(i)) = (array_val.clone(), index) {
-                    if (i as usize) < arr.len() {
-                        arr[i as usize] = new_val.clone();
-                        // update environment if needed
-                        // ...
-                        Value::Array(arr)
-                    } else {
-                        panic!("Index out of bounds");
+                match array {
+                    Value::Array(arr) => {
+                        let i = array_index(&index, arr.len());
+                        arr[i].clone()
                     }
-                } else {
-                    panic!("Invalid array assignment");
+                    Value::Map(entries) => map_get(&entries, &index),
+                    other => panic!("Cannot index into a non-array value: {}", other),
                 }
             }
 
+            // m.name looks up the entry with the key "name"
+            Expr::Field(target, field) => match self.eval(*target) {
+                Value::Map(entries) => map_get(&entries, &Value::Str(field)),
+                other => panic!(
+                    "Cannot read field '{}' of {} {}: only maps have fields",
+                    field,
+                    other.type_name(),
+                    other
+                ),
+            },
+
             // Arithmetic and string addition
             Expr::Add(left, right) => {
                 let left_val = self.eval(*left);
                 let right_val = self.eval(*right);
-                match (left_val, right_val) {
-                    (Value::Int(l), Value::Int(r)) => Value::Int(l + r),
-                    (Value::Double(l), Value::Double(r)) => Value::Double(l + r),
-                    (Value::Str(l), Value::Str(r)) => Value::Str(l + &r),
-                    (Value::Str(l), v) => Value::Str(l + &v.to_string()),
-                    (v, Value::Str(r)) => Value::Str(v.to_string() + &r),
-                    _ => panic!("Unsupported addition types"),
+                if let Some(sum) = self.int_arithmetic(&left_val, "+", &right_val) {
+                    return sum;
                 }
+                let result = match (left_val, right_val) {
+                    (Value::Double(l), Value::Double(r)) => return Value::Double(l + r),
+                    (Value::Str(l), Value::Str(r)) => l + &r,
+                    (Value::Str(l), v) => l + &v.to_string(),
+                    (v, Value::Str(r)) => v.to_string() + &r,
+                    (Value::Null, _) | (_, Value::Null) => null_operand("addition"),
+                    (Value::Bool(b), _) | (_, Value::Bool(b)) => bool_operand("addition", b),
+                    (l, r) => unsupported_operands("addition", &l, &r),
+                };
+                self.track_allocation(result.len());
+                Value::Str(result)
             }
 
             // Unary negation
             Expr::Neg(expr) => {
                 let val = self.eval(*expr);
                 match val {
-                    Value::Int(i) => Value::Int(-i),
+                    Value::Int(i) => match i.checked_neg() {
+                        Some(negated) => Value::Int(negated),
+                        None if self.big_ints => int_value(-BigInt::from(i)),
+                        None => int_out_of_range(&format!("-({})", i)),
+                    },
+                    Value::BigInt(n) => int_value(-n),
                     Value::Double(f) => Value::Double(-f),
-                    _ => panic!("Unsupported negation type"),
+                    Value::Null => null_operand("negation"),
+                    Value::Bool(b) => bool_operand("negation", b),
+                    other => panic!("Unsupported negation type: {}", other.type_name()),
                 }
             }
 
@@ -402,45 +2015,39 @@ impl Interpreter {
             Expr::Eq(left, right) => {
                 let l = self.eval(*left);
                 let r = self.eval(*right);
-                Value::Bool(l == r)
+                Value::Bool(values_equal(&l, &r))
             }
 
             // Inequality check
             Expr::Neq(left, right) => {
                 let l = self.eval(*left);
                 let r = self.eval(*right);
-                Value::Bool(l != r)
+                Value::Bool(!values_equal(&l, &r))
             }
 
             // Logical AND (short-circuiting)
             Expr::And(left, right) => {
-                if let Value::Bool(l) = self.eval(*left) {
-                    if !l {
-                        return Value::Bool(false);
-                    }
-                } else {
-                    panic!("Expected boolean in And");
-                }
-                if let Value::Bool(r) = self.eval(*right) {
-                    Value::Bool(r)
-                } else {
-                    panic!("Expected boolean in And");
+                if !self.eval(*left).expect_bool("and") {
+                    return Value::Bool(false);
                 }
+                Value::Bool(self.eval(*right).expect_bool("and"))
             }
 
             // Logical OR (short-circuiting)
             Expr::Or(left, right) => {
-                if let Value::Bool(l) = self.eval(*left) {
-                    if l {
-                        return Value::Bool(true);
-                    }
-                } else {
-                    panic!("Expected boolean in Or");
+                if self.eval(*left).expect_bool("or") {
+                    return Value::Bool(true);
                 }
-                if let Value::Bool(r) = self.eval(*right) {
-                    Value::Bool(r)
+                Value::Bool(self.eval(*right).expect_bool("or"))
+            }
+
+            // Defaulting (short-circuiting): the right side only runs if the left is falsy
+            Expr::Coalesce(left, right) => {
+                let l = self.eval(*left);
+                if l.is_truthy() {
+                    l
                 } else {
-                    panic!("Expected boolean in Or");
+                    self.eval(*right)
                 }
             }
 
@@ -457,10 +2064,14 @@ impl Interpreter {
             Expr::Sub(left, right) => {
                 let left_val = self.eval(*left);
                 let right_val = self.eval(*right);
+                if let Some(difference) = self.int_arithmetic(&left_val, "-", &right_val) {
+                    return difference;
+                }
                 match (left_val, right_val) {
-                    (Value::Int(l), Value::Int(r)) => Value::Int(l - r),
                     (Value::Double(l), Value::Double(r)) => Value::Double(l - r),
-                    _ => panic!("Unsupported subtraction types"),
+                    (Value::Null, _) | (_, Value::Null) => null_operand("subtraction"),
+                    (Value::Bool(b), _) | (_, Value::Bool(b)) => bool_operand("subtraction", b),
+                    (l, r) => unsupported_operands("subtraction", &l, &r),
                 }
             }
 
@@ -468,10 +2079,14 @@ impl Interpreter {
             Expr::Mul(left, right) => {
                 let left_val = self.eval(*left);
                 let right_val = self.eval(*right);
+                if let Some(product) = self.int_arithmetic(&left_val, "*", &right_val) {
+                    return product;
+                }
                 match (left_val, right_val) {
-                    (Value::Int(l), Value::Int(r)) => Value::Int(l * r),
                     (Value::Double(l), Value::Double(r)) => Value::Double(l * r),
-                    _ => panic!("Unsupported multiplication types"),
+                    (Value::Null, _) | (_, Value::Null) => null_operand("multiplication"),
+                    (Value::Bool(b), _) | (_, Value::Bool(b)) => bool_operand("multiplication", b),
+                    (l, r) => unsupported_operands("multiplication", &l, &r),
                 }
             }
 
@@ -479,20 +2094,19 @@ impl Interpreter {
             Expr::Div(left, right) => {
                 let left_val = self.eval(*left);
                 let right_val = self.eval(*right);
+                if let Some(quotient) = self.int_arithmetic(&left_val, "/", &right_val) {
+                    return quotient;
+                }
                 match (left_val, right_val) {
-                    (Value::Int(l), Value::Int(r)) => {
-                        if r == 0 {
-                            panic!("Division by zero");
-                        }
-                        Value::Int(l / r)
-                    }
                     (Value::Double(l), Value::Double(r)) => {
                         if r == 0.0 {
                             panic!("Division by zero");
                         }
                         Value::Double(l / r)
                     }
-                    _ => panic!("Unsupported division types"),
+                    (Value::Null, _) | (_, Value::Null) => null_operand("division"),
+                    (Value::Bool(b), _) | (_, Value::Bool(b)) => bool_operand("division", b),
+                    (l, r) => unsupported_operands("division", &l, &r),
                 }
             }
 
@@ -500,14 +2114,13 @@ impl Interpreter {
             Expr::Mod(left, right) => {
                 let left_val = self.eval(*left);
                 let right_val = self.eval(*right);
+                if let Some(remainder) = self.int_arithmetic(&left_val, "%", &right_val) {
+                    return remainder;
+                }
                 match (left_val, right_val) {
-                    (Value::Int(l), Value::Int(r)) => {
-                        if r == 0 {
-                            panic!("Modulo by zero");
-                        }
-                        Value::Int(l % r)
-                    }
-                    _ => panic!("Unsupported modulo types"),
+                    (Value::Null, _) | (_, Value::Null) => null_operand("modulo"),
+                    (Value::Bool(b), _) | (_, Value::Bool(b)) => bool_operand("modulo", b),
+                    (l, r) => unsupported_operands("modulo", &l, &r),
                 }
             }
 
@@ -515,78 +2128,45 @@ impl Interpreter {
             Expr::LessThan(left, right) => {
                 let l = self.eval(*left);
                 let r = self.eval(*right);
-                match (l, r) {
-                    (Value::Int(li), Value::Int(ri)) => Value::Bool(li < ri),
-                    (Value::Double(ld), Value::Double(rd)) => Value::Bool(ld < rd),
-                    _ => panic!("Unsupported types for LessThan comparison"),
-                }
+                Value::Bool(ordered("LessThan comparison", l, r, Ordering::is_lt))
             }
 
             // Comparison: greater than
             Expr::GreaterThan(left, right) => {
                 let l = self.eval(*left);
                 let r = self.eval(*right);
-                match (l, r) {
-                    (Value::Int(li), Value::Int(ri)) => Value::Bool(li > ri),
-                    (Value::Double(ld), Value::Double(rd)) => Value::Bool(ld > rd),
-                    _ => panic!("Unsupported types for GreaterThan comparison"),
-                }
+                Value::Bool(ordered("GreaterThan comparison", l, r, Ordering::is_gt))
             }
 
             // Comparison: less than or equal to
             Expr::LessEqual(left, right) => {
                 let l = self.eval(*left);
                 let r = self.eval(*right);
-                match (l, r) {
-                    (Value::Int(li), Value::Int(ri)) => Value::Bool(li <= ri),
-                    (Value::Double(ld), Value::Double(rd)) => Value::Bool(ld <= rd),
-                    _ => panic!("Unsupported types for LessEqual comparison"),
-                }
+                Value::Bool(ordered("LessEqual comparison", l, r, Ordering::is_le))
             }
 
             // Comparison: greater than or equal to
             Expr::GreaterEqual(left, right) => {
                 let l = self.eval(*left);
                 let r = self.eval(*right);
-                match (l, r) {
-                    (Value::Int(li), Value::Int(ri)) => Value::Bool(li >= ri),
-                    (Value::Double(ld), Value::Double(rd)) => Value::Bool(ld >= rd),
-                    _ => panic!("Unsupported types for GreaterEqual comparison"),
-                }
+                Value::Bool(ordered("GreaterEqual comparison", l, r, Ordering::is_ge))
             }
 
             Expr::FunctionCall(name, args) => {
-                if let Some(func_def) = self.functions.get(&name).cloned() {
-                    let mut func_interpreter = Interpreter::new();
-                    func_interpreter.functions = self.functions.clone();
-                    if args.len() != func_def.params.len() {
-                        panic!(
-                            "Function {} expects {} arguments, got {}",
-                            name,
-                            func_def.params.len(),
-                            args.len()
-                        );
-                    }
-                    for (i, (param_name, _param_type)) in func_def.params.iter().enumerate() {
-                        let arg_value = self.eval(args[i].clone());
-                        func_interpreter.env.insert(param_name.clone(), arg_value);
-                    }
-                    let mut return_value: Option<Value> = None;
-                    for stmt in func_def.body {
-                        let (out, control) = func_interpreter.execute_with_control(stmt);
-                        if let Some(output) = out {
-                            self.output_buffer.push_str(&output);
-                            self.output_buffer.push('\n');
-                        }
-                        if let ControlFlow::Return(val) = control {
-                            return_value = val;
-                            break;
-                        }
-                    }
-                    // If function has a return type, return the value, else return Int(0) by default
-                    return_value.unwrap_or(Value::Int(0))
+                if let Some(native) = self.natives.get(&name).cloned() {
+                    let arg_values = self.eval_list(args);
+                    call_native(&name, &native, arg_values)
+                } else if let Some(func_def) = self.functions.get(&name).cloned() {
+                    // Arguments are evaluated strictly left to right, all before the body runs,
+                    // so any output they produce comes before the function's own output
+                    let arg_values = self.eval_list_shared(args);
+                    self.call_user_function(&name, &func_def, arg_values)
+                } else if BUILTINS.contains(&name.as_str()) {
+                    self.call_builtin(&name, args)
                 } else {
-                    panic!("Undefined function: {}", name);
+                    let known = self.function_names();
+                    let suggestion = did_you_mean(&name, known.chain(BUILTINS.iter().copied()));
+                    panic!("Undefined function: {}{}", name, suggestion);
                 }
             }
         }
@@ -0,0 +1,133 @@
+// Fix linting warnings for unused code during development
+#![allow(dead_code)]
+// Converting between FinnLang values and JSON text for json_parse and json_stringify.
+// serde_json does the parsing and printing; the conversions go straight to and from `Value`
+// so object keys keep the order they're written in
+
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Error as _, Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use crate::interpreter::Value;
+
+// Parse JSON text: objects become maps, arrays arrays, whole numbers ints and other numbers
+// doubles. Invalid JSON is a runtime error
+pub fn parse(text: &str) -> Value {
+    match serde_json::from_str::<JsonValue>(text) {
+        Ok(JsonValue(value)) => value,
+        Err(err) => panic!("Invalid JSON passed to json_parse: {}", err),
+    }
+}
+
+// Write a value as compact JSON text. Doubles that JSON can't hold (NaN, infinities) are a
+// runtime error rather than quietly becoming null
+pub fn stringify(value: &Value) -> String {
+    serde_json::to_string(&JsonRef(value))
+        .unwrap_or_else(|err| panic!("json_stringify can't convert the value: {}", err))
+}
+
+// A value read from JSON
+struct JsonValue(Value);
+
+impl<'de> Deserialize<'de> for JsonValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor).map(JsonValue)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<Value, E> {
+        Ok(Value::Int(n))
+    }
+
+    // Only whole numbers too big for an int get here, so they're kept as doubles
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<Value, E> {
+        Ok(i64::try_from(n).map_or(Value::Double(n as f64), Value::Int))
+    }
+
+    fn visit_f64<E: de::Error>(self, n: f64) -> Result<Value, E> {
+        Ok(Value::Double(n))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::Str(s.to_string()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut elements = Vec::new();
+        while let Some(JsonValue(element)) = seq.next_element()? {
+            elements.push(element);
+        }
+        Ok(Value::Array(elements))
+    }
+
+    // A repeated key keeps its first position and its last value, like assigning twice
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut entries: Vec<(String, Value)> = Vec::new();
+        while let Some((key, JsonValue(value))) = map.next_entry::<String, JsonValue>()? {
+            match entries.iter_mut().find(|(k, _)| *k == key) {
+                Some(entry) => entry.1 = value,
+                None => entries.push((key, value)),
+            }
+        }
+        Ok(Value::Map(entries))
+    }
+}
+
+// A value being written as JSON
+struct JsonRef<'a>(&'a Value);
+
+impl Serialize for JsonRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Null => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Int(n) => serializer.serialize_i64(*n),
+            Value::Double(d) if !d.is_finite() => Err(S::Error::custom(format!(
+                "JSON has no way to write the double {}",
+                d
+            ))),
+            Value::Double(d) => serializer.serialize_f64(*d),
+            Value::Str(s) => serializer.serialize_str(s),
+            Value::Array(elements) => {
+                let mut seq = serializer.serialize_seq(Some(elements.len()))?;
+                for element in elements {
+                    seq.serialize_element(&JsonRef(element))?;
+                }
+                seq.end()
+            }
+            Value::Map(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, &JsonRef(value))?;
+                }
+                map.end()
+            }
+            Value::Ref(shared) => JsonRef(&shared.borrow()).serialize(serializer),
+            Value::BigInt(n) => match i128::try_from(n) {
+                Ok(n) => serializer.serialize_i128(n),
+                Err(_) => Err(S::Error::custom(format!(
+                    "the int {} is too big to write as JSON",
+                    n
+                ))),
+            },
+        }
+    }
+}
@@ -1,9 +1,13 @@
 #![allow(dead_code)]
+use std::rc::Rc;
+
 #[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 // Define the full set of token types that the language recognizes
 pub enum Token {
     // Keywords and types
     Let,
+    Once,
     Int,
     Bool,
     StringType,
@@ -13,14 +17,23 @@ pub enum Token {
     If,
     Elif,
     Else,
+    Try,
+    Catch,
+    TypeMatch,
+    Finally,
+    Assert,
+    AssertEq,
     Print,
+    PrintErr,
     Funct,
     Return,
+    Debug,
 
     // Literals
     Number(i64),
     Double(f64),
     BoolLiteral(bool),
+    Null,
     StrLiteral(String),
     Ident(String),
     // For arrays
@@ -43,9 +56,19 @@ pub enum Token {
     Neq,
     Not,
     Assign,
+    // Compound assignments: +=, -=, *=, /=, %=
+    PlusAssign,
+    MinusAssign,
+    StarAssign,
+    SlashAssign,
+    PercentAssign,
+    Arrow,
+    NullCoalesce,
 
     // Punctuation
     Colon,
+    Dot,
+    Ellipsis,
     Semicolon,
     LParen,
     RParen,
@@ -57,13 +80,62 @@ pub enum Token {
     Unknown(char),
 }
 
+// Every operator and punctuation token, longest first. The lexer takes the first entry that
+// matches, so it always reads the longest operator it can ("maximal munch"): `==` is never
+// two `=`s and `...` is never three `.`s. A new operator just needs adding above any entry
+// that is a prefix of it
+const OPERATORS: &[(&str, Token)] = &[
+    ("...", Token::Ellipsis),
+    ("==", Token::Eq),
+    ("=>", Token::Arrow),
+    ("!=", Token::Neq),
+    ("<=", Token::LessEqual),
+    (">=", Token::GreaterEqual),
+    ("&&", Token::And),
+    ("||", Token::Or),
+    ("??", Token::NullCoalesce),
+    ("+=", Token::PlusAssign),
+    ("-=", Token::MinusAssign),
+    ("*=", Token::StarAssign),
+    ("/=", Token::SlashAssign),
+    ("%=", Token::PercentAssign),
+    ("=", Token::Assign),
+    ("!", Token::Not),
+    ("<", Token::LessThan),
+    (">", Token::GreaterThan),
+    ("+", Token::Plus),
+    ("-", Token::Minus),
+    ("*", Token::Star),
+    ("/", Token::Slash),
+    ("%", Token::Percent),
+    (";", Token::Semicolon),
+    ("(", Token::LParen),
+    (")", Token::RParen),
+    ("{", Token::LBrace),
+    ("}", Token::RBrace),
+    ("[", Token::LBracket),
+    ("]", Token::RBracket),
+    (",", Token::Comma),
+    (":", Token::Colon),
+    (".", Token::Dot),
+];
+
 // The lexer takes source code and turns it into a stream of tokens
 #[derive(Clone)]
 pub struct Lexer {
-    // All characters of the input source
-    input: Vec<char>,
+    // All characters of the input source. Shared, so the parser can clone the lexer to peek
+    // at the next token without copying the whole source each time
+    input: Rc<[char]>,
     // Current position in the input
     position: usize,
+    // Current line and column (1-based), for error messages
+    line: usize,
+    column: usize,
+    // Line and column where the most recent token started
+    token_line: usize,
+    token_column: usize,
+    // Character offset where the most recent token started
+    token_start: usize,
 }
 
 impl Lexer {
@@ -72,127 +144,188 @@ impl Lexer {
         Lexer {
             input: input.chars().collect(),
             position: 0,
+            line: 1,
+            column: 1,
+            token_line: 1,
+            token_column: 1,
+            token_start: 0,
         }
     }
 
+    // Line and column where the most recently returned token started
+    pub fn token_position(&self) -> (usize, usize) {
+        (self.token_line, self.token_column)
+    }
+
+    // Character offsets of the most recently returned token in the source: where it starts and
+    // one past where it ends, so `==` spans two characters. They count chars, not bytes
+    pub fn token_span(&self) -> (usize, usize) {
+        (self.token_start, self.position.min(self.input.len()))
+    }
+
+    // Abort lexing with a message pointing at the current token
+    fn error(&self, message: &str) -> ! {
+        panic!(
+            "{} at line {}, column {}",
+            message, self.token_line, self.token_column
+        );
+    }
+
     // Peek at the current character without consuming it
     fn peek(&self) -> Option<char> {
         self.input.get(self.position).copied()
     }
 
-    // Advance the position and return the current character
+    // Advance the position and return the current character.
+    // Every character is one column wide, tabs included, the same way rustc and gcc count
+    // them. Columns then mean "characters into the line" whatever tab width an editor uses,
+    // and a caret can be lined up under a token by copying the line's leading tabs.
+    // This is the only place the position moves, so newlines are counted the same in strings
+    // and comments as anywhere else. A \r on its own ends a line too (\r\n counts once)
     fn advance(&mut self) -> Option<char> {
-        let ch = self.peek();
+        let ch = self.peek()?;
         self.position += 1;
-        ch
+        match ch {
+            '\n' => {
+                self.line += 1;
+                self.column = 1;
+            }
+            '\r' if self.peek() != Some('\n') => {
+                self.line += 1;
+                self.column = 1;
+            }
+            _ => self.column += 1,
+        }
+        Some(ch)
     }
 
-    // Skip any whitespace characters (space, tab, newline)
-    fn skip_whitespace(&mut self) {
-        while let Some(c) = self.peek() {
-            if c.is_whitespace() {
-                self.advance();
-            } else {
-                break;
+    // Read the two hex digits of a \xHH escape, e.g. \x41 is 'A'
+    fn hex_escape(&mut self) -> char {
+        let mut digits = String::new();
+        for _ in 0..2 {
+            match self.peek() {
+                Some(c) if c.is_ascii_hexdigit() => digits.push(self.advance().unwrap()),
+                _ => self.error("Unexpected escape sequence: \\x needs exactly two hex digits"),
             }
         }
+        // Two hex digits are at most 0xFF, which is always a valid char
+        char::from_u32(u32::from_str_radix(&digits, 16).unwrap()).unwrap()
     }
 
-    // Get the next token from the source input
-    pub fn next_token(&mut self) -> Token {
-        self.skip_whitespace();
-
-        let ch = self.advance();
-
-        match ch {
-            // Handle '=' or '=='
-            Some('=') => {
-                if self.peek() == Some('=') {
-                    self.advance();
-                    Token::Eq
-                } else {
-                    Token::Assign
-                }
+    // Read the braced hex code point of a \u{...} escape, e.g. \u{1F600} is 😀
+    fn unicode_escape(&mut self) -> char {
+        if self.peek() != Some('{') {
+            self.error("Unexpected escape sequence: \\u must be followed by {hex digits}");
+        }
+        self.advance(); // consume '{'
+        let mut digits = String::new();
+        while let Some(c) = self.peek() {
+            if c == '}' {
+                break;
             }
-
-            // Logical AND &&
-            Some('&') => {
-                if self.peek() == Some('&') {
-                    self.advance();
-                    Token::And
-                } else {
-                    Token::Unknown('&')
-                }
+            if !c.is_ascii_hexdigit() || digits.len() == 6 {
+                self.error("Unexpected escape sequence: \\u{...} needs 1 to 6 hex digits");
             }
+            digits.push(self.advance().unwrap());
+        }
+        if self.advance() != Some('}') || digits.is_empty() {
+            self.error("Unexpected escape sequence: \\u{...} needs 1 to 6 hex digits");
+        }
+        let code = u32::from_str_radix(&digits, 16).unwrap();
+        char::from_u32(code).unwrap_or_else(|| {
+            self.error(&format!(
+                "Unexpected escape sequence: \\u{{{}}} is not a valid unicode character",
+                digits
+            ))
+        })
+    }
 
-            // Logical OR ||
-            Some('|') => {
-                if self.peek() == Some('|') {
+    // Skip whitespace (space, tab, newline) and comments: // to the end of the line, and
+    // /* ... */, which can nest
+    fn skip_whitespace_and_comments(&mut self) {
+        loop {
+            match (self.peek(), self.input.get(self.position + 1)) {
+                (Some(c), _) if c.is_whitespace() => {
                     self.advance();
-                    Token::Or
-                } else {
-                    Token::Unknown('|')
                 }
-            }
-
-            // Basic operators and symbols
-            Some('+') => Token::Plus,
-            Some('-') => Token::Minus,
-            Some('*') => Token::Star,
-            // Handle '/' for division or comments
-            Some('/') => {
-                match self.peek() {
-                    // Single-line comment //
-                    Some('/') => {
-                        self.advance(); // consume second '/'
-                                        // Skip until end of line
-                        while let Some(c) = self.peek() {
-                            if c == '\n' {
-                                break;
-                            }
-                            self.advance();
+                (Some('/'), Some('/')) => {
+                    while let Some(c) = self.peek() {
+                        if c == '\n' {
+                            break;
                         }
-                        // Recursively get the next token after the comment
-                        self.next_token()
+                        self.advance();
                     }
-                    // Multi-line comment /*
-                    Some('*') => {
-                        self.advance(); // consume '*'
-                        let mut depth = 1;
-
-                        while depth > 0 {
-                            match self.advance() {
-                                Some('/') if self.peek() == Some('*') => {
-                                    self.advance(); // consume '*'
-                                    depth += 1; // nested comment
-                                }
-                                Some('*') if self.peek() == Some('/') => {
-                                    self.advance(); // consume '/'
-                                    depth -= 1; // end of comment block
-                                }
-                                None => break, // End of input
-                                _ => {}        // continue
+                }
+                (Some('/'), Some('*')) => {
+                    self.advance(); // consume '/'
+                    self.advance(); // consume '*'
+                    let mut depth = 1;
+                    while depth > 0 {
+                        match self.advance() {
+                            Some('/') if self.peek() == Some('*') => {
+                                self.advance(); // consume '*'
+                                depth += 1; // nested comment
+                            }
+                            Some('*') if self.peek() == Some('/') => {
+                                self.advance(); // consume '/'
+                                depth -= 1; // end of comment block
                             }
+                            None => break, // End of input
+                            _ => {}        // continue
                         }
-                        // Recursively get the next token after the comment
-                        self.next_token()
                     }
-                    // Regular division
-                    _ => Token::Slash,
                 }
+                _ => return,
             }
-            Some('%') => Token::Percent,
-            Some(';') => Token::Semicolon,
-            Some('(') => Token::LParen,
-            Some(')') => Token::RParen,
-            Some('{') => Token::LBrace,
-            Some('}') => Token::RBrace,
-            // for arrays
-            Some('[') => Token::LBracket,
-            Some(']') => Token::RBracket,
-            Some(',') => Token::Comma,
-            Some(':') => Token::Colon,
+        }
+    }
 
+    // Length of the exponent (e or E, an optional sign, then digits) at the current position,
+    // or 0 if there isn't one. Without digits the 'e' isn't part of the number, so `2e` is still
+    // 2 followed by the name e
+    fn exponent_len(&self) -> usize {
+        let rest = &self.input[self.position..];
+        if !matches!(rest.first(), Some('e' | 'E')) {
+            return 0;
+        }
+        let sign = usize::from(matches!(rest.get(1), Some('+' | '-')));
+        let digits = rest[1 + sign..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        if digits == 0 {
+            0
+        } else {
+            1 + sign + digits
+        }
+    }
+
+    // Read the operator or punctuation at the current position, if there is one, taking the
+    // first (so longest) entry of OPERATORS that matches
+    fn read_operator(&mut self) -> Option<Token> {
+        let rest = &self.input[self.position..];
+        let (text, token) = OPERATORS.iter().find(|(text, _)| {
+            text.chars().count() <= rest.len() && text.chars().zip(rest).all(|(a, b)| a == *b)
+        })?;
+        for _ in text.chars() {
+            self.advance();
+        }
+        Some(token.clone())
+    }
+
+    // Get the next token from the source input
+    pub fn next_token(&mut self) -> Token {
+        self.skip_whitespace_and_comments();
+
+        self.token_line = self.line;
+        self.token_column = self.column;
+        self.token_start = self.position;
+        if let Some(token) = self.read_operator() {
+            return token;
+        }
+        let ch = self.advance();
+
+        match ch {
             // Handle string literals
             Some('"') => {
                 let mut s = String::new();
@@ -200,6 +333,22 @@ impl Lexer {
                     if next == '"' {
                         self.advance(); // Consume closing quote
                         break;
+                    } else if next == '\\' {
+                        self.advance(); // Consume backslash
+                        match self.advance() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some('r') => s.push('\r'),
+                            Some('0') => s.push('\0'),
+                            Some('"') => s.push('"'),
+                            Some('\\') => s.push('\\'),
+                            Some('x') => s.push(self.hex_escape()),
+                            Some('u') => s.push(self.unicode_escape()),
+                            Some(other) => {
+                                self.error(&format!("Unexpected escape sequence '\\{}'", other))
+                            }
+                            None => break,
+                        }
                     } else {
                         s.push(self.advance().unwrap());
                     }
@@ -223,18 +372,38 @@ impl Lexer {
                     }
                 }
 
+                // An exponent makes the literal a double even without a '.', e.g. 1e3 or 2.5E-7
+                let exponent = self.exponent_len();
+                if exponent > 0 {
+                    is_float = true;
+                    for _ in 0..exponent {
+                        num.push(self.advance().unwrap());
+                    }
+                }
+
+                // Out-of-range literals are lexer errors rather than a crash in parse()
                 if is_float {
-                    Token::Double(num.parse::<f64>().unwrap())
+                    match num.parse::<f64>() {
+                        Ok(f) if f.is_finite() => Token::Double(f),
+                        _ => self.error(&format!("Double literal {} is too large", num)),
+                    }
                 } else {
-                    Token::Number(num.parse::<i64>().unwrap())
+                    match num.parse::<i64>() {
+                        Ok(n) => Token::Number(n),
+                        Err(_) => self.error(&format!(
+                            "Integer literal {} is too large (the maximum is {})",
+                            num,
+                            i64::MAX
+                        )),
+                    }
                 }
             }
 
             // Handle identifiers and keywords
-            Some(c) if c.is_ascii_alphabetic() => {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
                 let mut ident = c.to_string();
                 while let Some(next) = self.peek() {
-                    if next.is_ascii_alphanumeric() {
+                    if next.is_ascii_alphanumeric() || next == '_' {
                         ident.push(self.advance().unwrap());
                     } else {
                         break;
@@ -244,7 +413,9 @@ impl Lexer {
                 // Match keywords and return appropriate token
                 match ident.as_str() {
                     "let" => Token::Let,
+                    "once" => Token::Once,
                     "woof" => Token::Print,
+                    "howl" => Token::PrintErr,
                     "int" => Token::Int,
                     "bool" => Token::Bool,
                     "string" => Token::StringType,
@@ -254,35 +425,24 @@ impl Lexer {
                     "if" => Token::If,
                     "elif" => Token::Elif,
                     "else" => Token::Else,
+                    "try" => Token::Try,
+                    "catch" => Token::Catch,
+                    "typematch" => Token::TypeMatch,
+                    "finally" => Token::Finally,
+                    "assert" => Token::Assert,
+                    "assert_eq" => Token::AssertEq,
                     "funct" => Token::Funct,
                     "return" => Token::Return,
+                    "debug" => Token::Debug,
                     "and" => Token::And,
                     "or" => Token::Or,
                     "true" => Token::BoolLiteral(true),
                     "false" => Token::BoolLiteral(false),
+                    "null" => Token::Null,
                     _ => Token::Ident(ident),
                 }
             }
 
-            // Handle relational operators
-            Some('<') => {
-                if self.peek() == Some('=') {
-                    self.advance();
-                    Token::LessEqual
-                } else {
-                    Token::LessThan
-                }
-            }
-
-            Some('>') => {
-                if self.peek() == Some('=') {
-                    self.advance();
-                    Token::GreaterEqual
-                } else {
-                    Token::GreaterThan
-                }
-            }
-
             // End of input
             None => Token::EOF,
 
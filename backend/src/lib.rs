@@ -1,16 +1,39 @@
 mod ast;
+mod builtins;
 mod interpreter;
+mod json;
 mod lexer;
+mod lint;
 mod parser;
+mod program;
+mod typecheck;
+mod visitor;
 
-use interpreter::Interpreter;
-use lexer::Lexer;
+use interpreter::{panic_message, visible_variables, Interpreter, NativeFunction, StatementHook};
+use lexer::{Lexer, Token};
 use parser::Parser;
 use std::panic;
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub use ast::{Accessor, Expr, LValue, Stmt, Type};
+pub use interpreter::Value;
+pub use lint::Warning;
+pub use program::{Program, Symbol, SymbolKind};
+pub use typecheck::TypeError;
+
+// Function calls, and copying and freeing nested values, recurse through the Rust stack, so
+// programs need far more of it than a thread gets by default. The CLI and server run them on
+// threads with this much. Only the part a program actually uses is backed by memory
+pub const RUN_STACK_SIZE: usize = 256 * 1024 * 1024;
 
 #[derive(Debug)]
 pub enum FinnLangError {
     ParseError(String),
+    // Every type error in a program, found before it started running
+    TypeErrors(Vec<TypeError>),
     RuntimeError(String),
 }
 
@@ -18,6 +41,13 @@ impl std::fmt::Display for FinnLangError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             FinnLangError::ParseError(msg) => write!(f, "Parse Error: {}", msg),
+            FinnLangError::TypeErrors(errors) => {
+                let lines: Vec<String> = errors
+                    .iter()
+                    .map(|error| format!("Type Error: {}", error))
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
             FinnLangError::RuntimeError(msg) => write!(f, "Runtime Error: {}", msg),
         }
     }
@@ -25,34 +55,479 @@ impl std::fmt::Display for FinnLangError {
 
 impl std::error::Error for FinnLangError {}
 
+// Everything a program printed, split into woof() output and howl() output
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
+
 pub fn run_finn_code(source: &str) -> Result<String, FinnLangError> {
-    // Catch panics and convert them to errors
-    let result = panic::catch_unwind(|| {
-        let lexer = Lexer::new(source);
-        let mut parser = Parser::new(lexer);
-        let program = parser.parse();
+    run_finn_code_with_stderr(source).map(|output| output.stdout)
+}
 
-        let mut interpreter = Interpreter::new();
-        interpreter.run(program)
+// Like run_finn_code, but also returns what the program wrote with howl()
+pub fn run_finn_code_with_stderr(source: &str) -> Result<RunOutput, FinnLangError> {
+    run_finn_code_timed(source).0
+}
+
+// Like run_finn_code_with_stderr, but with lines of input for the program to read one at a
+// time with read_line(). Nothing is read from the real stdin
+pub fn run_finn_code_with_input(
+    source: &str,
+    input: Vec<String>,
+) -> Result<RunOutput, FinnLangError> {
+    let options = RunOptions {
+        input,
+        ..RunOptions::default()
+    };
+    run_finn_code_with_options(source, &options).0
+}
+
+// How long each stage of a run took
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    // Lexing and parsing, and with `typecheck` the static checks too
+    pub parse: Duration,
+    // Interpreting the parsed program
+    pub run: Duration,
+}
+
+// Like run_finn_code_with_stderr, but also times parsing and running separately
+pub fn run_finn_code_timed(source: &str) -> (Result<RunOutput, FinnLangError>, Timings) {
+    run_finn_code_with_options(source, &RunOptions::default())
+}
+
+// How to run a program. Every setting can be combined with the others, and the default is a
+// plain run: no modes, no input, and no file access or colors
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    // Debug mode: each debug; statement writes the variables in scope to stderr
+    pub debug: bool,
+    // Big int mode: int arithmetic whose result doesn't fit in 64 bits gives the exact answer
+    // instead of an overflow error
+    pub big_ints: bool,
+    // Strict mode: printing the result of a function with no return type is an error rather
+    // than printing null
+    pub strict: bool,
+    // Type check the program first. If there are any type errors it doesn't run at all, and
+    // they all come back together in FinnLangError::TypeErrors
+    pub typecheck: bool,
+    // Assigning to a variable that doesn't exist declares it, so `x = 5;` works without a `let`
+    pub implicit_declare: bool,
+    // Let read_file and write_file use the file system, as the CLI does. Leave it off for code
+    // that isn't as trusted as whoever runs it, like code sent to the server
+    pub file_access: bool,
+    // Make color() add ANSI escape codes
+    pub colors: bool,
+    // Lines for read_line() to give out one at a time. Nothing is read from the real stdin
+    pub input: Vec<String>,
+    // A function to call once the program has run, with string arguments, the way the CLI's
+    // `--call name arg...` does. A wrong number of arguments is a runtime error like any other
+    pub call: Option<(String, Vec<String>)>,
+    // Stops the program with a runtime error once it's set, from any thread. Loops and calls
+    // check it, so this is how to stop a program that might never end, like one on a timer
+    pub stop: Option<Arc<AtomicBool>>,
+}
+
+impl RunOptions {
+    // Set up an interpreter to run the way these options say
+    fn apply(&self, interpreter: &mut Interpreter) {
+        interpreter.set_debug_mode(self.debug);
+        interpreter.set_big_ints(self.big_ints);
+        interpreter.set_strict(self.strict);
+        interpreter.set_implicit_declare(self.implicit_declare);
+        interpreter.set_file_access(self.file_access);
+        interpreter.set_colors(self.colors);
+        interpreter.set_input(self.input.clone());
+        if let Some(stop) = &self.stop {
+            interpreter.set_stop_flag(Arc::clone(stop));
+        }
+    }
+}
+
+// Like run_finn_code_timed, but run the way `options` says
+pub fn run_finn_code_with_options(
+    source: &str,
+    options: &RunOptions,
+) -> (Result<RunOutput, FinnLangError>, Timings) {
+    run_program_timed(source, options, run_to_output)
+}
+
+// A statement that's about to run, as passed to run_finn_code_traced's callback
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    // What kind of statement it is, e.g. "let", "woof" or "assignment"
+    pub statement: &'static str,
+    // The variables it can see and their printed values, sorted by name. A name declared in
+    // more than one scope shows the innermost one
+    pub variables: Vec<(String, String)>,
+}
+
+// Like run_finn_code_with_options, but calls `on_step` before every statement that runs,
+// including each pass through a loop body and every statement of every function call
+pub fn run_finn_code_traced(
+    source: &str,
+    options: &RunOptions,
+    mut on_step: impl FnMut(&TraceStep) + 'static,
+) -> (Result<RunOutput, FinnLangError>, Timings) {
+    let hook: StatementHook = Box::new(move |stmt, scopes| {
+        let variables = visible_variables(scopes)
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        on_step(&TraceStep {
+            statement: stmt.kind(),
+            variables,
+        });
     });
+    run_program_timed(source, options, |interpreter, program| {
+        interpreter.set_statement_hook(hook);
+        run_to_output(interpreter, program)
+    })
+}
+
+// Parse a program, check its types if `options` asks for that, and run it in an interpreter
+// set up from `options`. `run` gets the interpreter and the statements and does the running,
+// so each kind of run can add its own setup and collect its own results. A runtime error
+// comes back as an Err, and so does a parse error or any type error
+fn run_program_timed<T>(
+    source: &str,
+    options: &RunOptions,
+    run: impl FnOnce(&mut Interpreter, Vec<Stmt>) -> T,
+) -> (Result<T, FinnLangError>, Timings) {
+    let mut timings = Timings::default();
+
+    let start = Instant::now();
+    let program = parse_for_run(source, options);
+    timings.parse = start.elapsed();
+    let mut program: Vec<Stmt> = match program {
+        Ok(program) => program.into_iter().map(|(_, stmt)| stmt).collect(),
+        Err(error) => return (Err(error), timings),
+    };
+
+    // The call runs like one more statement at the end of the file
+    if let Some((function, args)) = &options.call {
+        let args = args.iter().cloned().map(Expr::StrLiteral).collect();
+        program.push(Stmt::ExprStmt(Expr::FunctionCall(function.clone(), args)));
+    }
+
+    // Catch panics and convert them to errors
+    let start = Instant::now();
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let mut interpreter = Interpreter::new();
+        options.apply(&mut interpreter);
+        run(&mut interpreter, program)
+    }));
+    timings.run = start.elapsed();
+
+    (result.map_err(panic_to_error), timings)
+}
+
+// Parse a program to run, checking its types first if `options` asks for that
+fn parse_for_run(source: &str, options: &RunOptions) -> Result<Vec<(usize, Stmt)>, FinnLangError> {
+    if !options.typecheck {
+        return parse_with_lines(source).map(|(statements, _)| statements);
+    }
+    let program = parse_finn_code(source)?;
+    if program.type_errors.is_empty() {
+        Ok(program.statements)
+    } else {
+        Err(FinnLangError::TypeErrors(program.type_errors))
+    }
+}
 
-    match result {
-        Ok(output) => Ok(output),
-        Err(panic_info) => {
-            let error_msg = if let Some(s) = panic_info.downcast_ref::<&str>() {
-                s.to_string()
-            } else if let Some(s) = panic_info.downcast_ref::<String>() {
-                s.clone()
-            } else {
-                "Unknown compilation/runtime error occurred".to_string()
-            };
-
-            // Determine if it's a parse error or runtime error based on content
-            if error_msg.contains("Expected") || error_msg.contains("Unexpected") {
-                Err(FinnLangError::ParseError(error_msg))
-            } else {
-                Err(FinnLangError::RuntimeError(error_msg))
+// Run a program to the end and collect what it printed
+fn run_to_output(interpreter: &mut Interpreter, program: Vec<Stmt>) -> RunOutput {
+    let stdout = interpreter.run(program);
+    RunOutput {
+        stdout,
+        stderr: interpreter.take_error_output(),
+    }
+}
+
+// Static warnings for code that parses but is probably a mistake: code after a return,
+// variables that are never read, lets that hide another variable, and code the parser skipped
+pub fn check_finn_code(source: &str) -> Result<Vec<Warning>, FinnLangError> {
+    parse_finn_code(source).map(|program| program.diagnostics)
+}
+
+// Parse a program without running it, along with its warnings and the names it declares
+pub fn parse_finn_code(source: &str) -> Result<Program, FinnLangError> {
+    let (statements, diagnostics) = parse_with_lines(source)?;
+    Ok(Program::new(source, statements, diagnostics))
+}
+
+// One token of a program and exactly where it is in the source, for tools like an editor
+// underlining the token an error points at
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenInfo {
+    // The token's type, e.g. "Ident", "Number" or "Eq"
+    pub kind: String,
+    // The source text it was read from, e.g. "==" or "\"hi\"" including the quotes
+    pub text: String,
+    pub line: usize,
+    pub column: usize,
+    // Character (not byte) offsets of the token's first character and one past its last
+    pub start: usize,
+    pub end: usize,
+}
+
+// Split a program into tokens with their positions, without parsing it. A character the
+// lexer can't read is an error
+pub fn tokenize_finn_code(source: &str) -> Result<Vec<TokenInfo>, FinnLangError> {
+    panic::catch_unwind(|| {
+        let chars: Vec<char> = source.chars().collect();
+        let mut lexer = Lexer::new(source);
+        let mut tokens = Vec::new();
+        loop {
+            let token = lexer.next_token();
+            if token == Token::EOF {
+                return tokens;
+            }
+            let (line, column) = lexer.token_position();
+            if let Token::Unknown(ch) = token {
+                panic!(
+                    "Unexpected character '{}' at line {}, column {}",
+                    ch, line, column
+                );
             }
+            let (start, end) = lexer.token_span();
+            let kind = format!("{:?}", token);
+            tokens.push(TokenInfo {
+                // Just the variant's name, without its value
+                kind: kind.split('(').next().unwrap_or_default().to_string(),
+                text: chars[start..end].iter().collect(),
+                line,
+                column,
+                start,
+                end,
+            });
         }
+    })
+    .map_err(|panic_info| FinnLangError::ParseError(panic_message(panic_info.as_ref())))
+}
+
+// Result of a best-effort run: everything printed before the first error, plus that
+// error and the line of the top-level statement that raised it
+#[derive(Debug)]
+pub struct PartialRun {
+    pub output: RunOutput,
+    pub error: Option<FinnLangError>,
+    // None for parse and type errors, whose messages already include their positions
+    pub error_line: Option<usize>,
+}
+
+// Run code, keeping the output produced before a runtime error instead of discarding it
+pub fn run_finn_code_best_effort(source: &str) -> PartialRun {
+    run_finn_code_best_effort_with_input(source, Vec::new())
+}
+
+// Like run_finn_code_best_effort, with lines of input for read_line() as in
+// run_finn_code_with_input
+pub fn run_finn_code_best_effort_with_input(source: &str, input: Vec<String>) -> PartialRun {
+    let options = RunOptions {
+        input,
+        ..RunOptions::default()
+    };
+    run_finn_code_best_effort_with_options(source, &options)
+}
+
+// Like run_finn_code_best_effort, but run the way `options` says. Every option applies except
+// `call`, since there'd be no line to report if the call failed
+pub fn run_finn_code_best_effort_with_options(source: &str, options: &RunOptions) -> PartialRun {
+    let statements = match parse_for_run(source, options) {
+        Ok(statements) => statements,
+        Err(error) => {
+            return PartialRun {
+                output: RunOutput {
+                    stdout: String::new(),
+                    stderr: String::new(),
+                },
+                error: Some(error),
+                error_line: None,
+            }
+        }
+    };
+
+    let mut interpreter = Interpreter::new();
+    options.apply(&mut interpreter);
+    let (stdout, failure) = interpreter.run_best_effort(statements);
+    let (error, error_line) = match failure {
+        Some((panic_info, line)) => (Some(panic_to_error(panic_info)), Some(line)),
+        None => (None, None),
+    };
+    PartialRun {
+        output: RunOutput {
+            stdout,
+            stderr: interpreter.take_error_output(),
+        },
+        error,
+        error_line,
+    }
+}
+
+// Results of running a file in test mode
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestReport {
+    pub output: RunOutput,
+    pub passed: usize,
+    // Messages of the assertions that failed, in the order they ran
+    pub failures: Vec<String>,
+}
+
+// Run code in test mode: failed assertions are collected instead of stopping the program
+pub fn run_finn_tests(source: &str) -> Result<TestReport, FinnLangError> {
+    run_finn_tests_with_options(source, &RunOptions::default()).0
+}
+
+// Like run_finn_tests, but run the way `options` says, and also timed
+pub fn run_finn_tests_with_options(
+    source: &str,
+    options: &RunOptions,
+) -> (Result<TestReport, FinnLangError>, Timings) {
+    run_program_timed(source, options, |interpreter, program| {
+        interpreter.set_test_mode(true);
+        let output = run_to_output(interpreter, program);
+        let (passed, failures) = interpreter.assertion_results();
+        TestReport {
+            output,
+            passed,
+            failures: failures.to_vec(),
+        }
+    })
+}
+
+// Runs programs that can call functions written in Rust, for applications embedding FinnLang:
+//
+//     let mut runner = NativeRunner::new();
+//     runner.register_builtin("double_it", |args| match args.as_slice() {
+//         [Value::Int(n)] => Ok(Value::Int(n * 2)),
+//         _ => Err("expects one int".to_string()),
+//     });
+//     runner.run("woof(double_it(21));") // Ok, with stdout "42"
+//
+// See Interpreter::register_builtin for how the names interact with the program's own
+#[derive(Default)]
+pub struct NativeRunner {
+    natives: Vec<(String, NativeFunction)>,
+}
+
+impl NativeRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Make `function` callable as `name(...)` in every program this runner runs. It gets the
+    // evaluated arguments; returning Err stops the program with a runtime error
+    pub fn register_builtin(
+        &mut self,
+        name: &str,
+        function: impl Fn(Vec<Value>) -> Result<Value, String> + 'static,
+    ) {
+        self.natives.push((name.to_string(), Rc::new(function)));
+    }
+
+    // Run a program like run_finn_code_with_stderr, with the registered functions available
+    pub fn run(&self, source: &str) -> Result<RunOutput, FinnLangError> {
+        // Runtime errors are panics that come back as an Err, so Rust's default panic message
+        // would only be noise. The hook is global, so whatever was there is put back after
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        let result = run_program_timed(source, &RunOptions::default(), |interpreter, program| {
+            for (name, function) in &self.natives {
+                let function = Rc::clone(function);
+                interpreter.register_builtin(name, move |args| function(args));
+            }
+            run_to_output(interpreter, program)
+        })
+        .0;
+        panic::set_hook(hook);
+        result
+    }
+}
+
+// A REPL session that keeps variables and functions between inputs
+// and echoes the value of bare expression statements
+pub struct ReplSession {
+    interpreter: Interpreter,
+}
+
+impl ReplSession {
+    pub fn new() -> Self {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_echo_expressions(true);
+        interpreter.set_debug_mode(true);
+        ReplSession { interpreter }
+    }
+
+    // Allow or forbid read_file and write_file for the rest of the session, like
+    // RunOptions::file_access. A new session has no file access
+    pub fn set_file_access(&mut self, file_access: bool) {
+        self.interpreter.set_file_access(file_access);
+    }
+
+    // Turn color() output on or off for the rest of the session, like RunOptions::colors
+    pub fn set_colors(&mut self, colors: bool) {
+        self.interpreter.set_colors(colors);
+    }
+
+    // Run one chunk of REPL input against the session's interpreter
+    pub fn run(&mut self, source: &str) -> Result<RunOutput, FinnLangError> {
+        let program = parse_program(source)?;
+        let interpreter = &mut self.interpreter;
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let stdout = interpreter.run(program);
+            RunOutput {
+                stdout,
+                stderr: interpreter.take_error_output(),
+            }
+        }));
+
+        result.map_err(|payload| {
+            interpreter.recover_from_error();
+            panic_to_error(payload)
+        })
+    }
+}
+
+impl Default for ReplSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Lex and parse source code into statements ready to run. It skips the lint and type checks
+// parse_finn_code does, since running a program doesn't need their results
+fn parse_program(source: &str) -> Result<Vec<Stmt>, FinnLangError> {
+    let (statements, _) = parse_with_lines(source)?;
+    Ok(statements.into_iter().map(|(_, stmt)| stmt).collect())
+}
+
+// Top-level statements paired with the line each starts on, and the parser's warnings about
+// code it skipped
+type ParsedSource = (Vec<(usize, Stmt)>, Vec<Warning>);
+
+// Lex and parse source code without running any checks. Anything that goes wrong here is a
+// parse error, whatever its message says
+fn parse_with_lines(source: &str) -> Result<ParsedSource, FinnLangError> {
+    panic::catch_unwind(|| {
+        let lexer = Lexer::new(source);
+        let mut parser = Parser::new(lexer);
+        let statements = parser.parse_with_lines();
+        (statements, parser.take_diagnostics())
+    })
+    .map_err(|panic_info| FinnLangError::ParseError(panic_message(panic_info.as_ref())))
+}
+
+// Turn a caught panic into a FinnLangError
+fn panic_to_error(panic_info: Box<dyn std::any::Any + Send>) -> FinnLangError {
+    let error_msg = panic_message(panic_info.as_ref());
+
+    // Determine if it's a parse error or runtime error based on content
+    if error_msg.contains("Expected") || error_msg.contains("Unexpected") {
+        FinnLangError::ParseError(error_msg)
+    } else {
+        FinnLangError::RuntimeError(error_msg)
     }
 }
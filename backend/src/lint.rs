@@ -0,0 +1,259 @@
+// Fix linting warnings for unused code during development
+#![allow(dead_code)]
+// Static checks that find likely mistakes without running the program. They only warn:
+// the program still runs as written
+
+use std::fmt;
+
+use crate::ast::Stmt;
+use crate::visitor::{walk_block, walk_stmt, Visitor};
+
+// A problem found by a check, with its line: a declaration's own line for unused and shadowed
+// variables, otherwise the line of the top-level statement it's in (the same line a runtime
+// error in that statement would be reported on)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub message: String,
+    pub line: usize,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (line {})", self.message, self.line)
+    }
+}
+
+// Run every check over a program (top-level statements paired with their lines),
+// returning the warnings in line order
+pub fn check_program(program: &[(usize, Stmt)]) -> Vec<Warning> {
+    let mut unreachable = UnreachableCode::default();
+    let mut bindings = Bindings::default();
+    bindings.enter_scope();
+    for (line, stmt) in program {
+        unreachable.line = *line;
+        unreachable.visit_stmt(stmt);
+        bindings.line = *line;
+        bindings.visit_stmt(stmt);
+    }
+    bindings.exit_scope();
+
+    // Top-level statements have their own lines, so dead code there points at its first line
+    if let Some(i) = program
+        .iter()
+        .position(|(_, s)| matches!(s, Stmt::Return(_)))
+    {
+        if let Some((line, _)) = program.get(i + 1) {
+            unreachable.line = *line;
+            unreachable.report(program.len() - i - 1);
+        }
+    }
+
+    let mut warnings = unreachable.warnings;
+    warnings.append(&mut bindings.warnings);
+    // Stable, so warnings on the same line keep the order their checks found them in
+    warnings.sort_by_key(|warning| warning.line);
+    warnings
+}
+
+// Flags statements that come after a `return` in the same block, since they can never run
+#[derive(Default)]
+struct UnreachableCode {
+    // Line of the top-level statement being checked
+    line: usize,
+    // Function whose body is being checked, if any
+    function: Option<String>,
+    warnings: Vec<Warning>,
+}
+
+impl UnreachableCode {
+    fn report(&mut self, dead: usize) {
+        let place = match &self.function {
+            Some(name) => format!("in function '{}'", name),
+            None => "at the top level".to_string(),
+        };
+        self.warnings.push(Warning {
+            message: format!(
+                "Unreachable code: {} statement{} after return {} will never run",
+                dead,
+                if dead == 1 { "" } else { "s" },
+                place
+            ),
+            line: self.line,
+        });
+    }
+}
+
+impl Visitor for UnreachableCode {
+    fn visit_function_def(&mut self, name: &str, body: &[Stmt]) {
+        let outer = self.function.replace(name.to_string());
+        self.visit_block(body);
+        self.function = outer;
+    }
+
+    fn visit_block(&mut self, stmts: &[Stmt]) {
+        if let Some(i) = stmts.iter().position(|s| matches!(s, Stmt::Return(_))) {
+            let dead = stmts.len() - i - 1;
+            if dead > 0 {
+                self.report(dead);
+            }
+        }
+        walk_block(self, stmts);
+    }
+}
+
+// A variable declared in the scope being checked
+struct Binding {
+    name: String,
+    line: usize,
+    // Declared with `let` (loop variables, parameters and catch variables are never
+    // reported as unused, since they often have to exist whether or not they're read)
+    from_let: bool,
+    read: bool,
+}
+
+// Flags `let`s whose variable is never read, and `let`s that hide a variable of the same
+// name that's still in scope. Scopes follow the interpreter's: blocks, try/catch/finally
+// clauses and each pass of a loop body get their own, and a function body can't see outside
+// itself
+#[derive(Default)]
+struct Bindings {
+    // Line of the top-level statement being checked
+    line: usize,
+    scopes: Vec<Vec<Binding>>,
+    warnings: Vec<Warning>,
+}
+
+impl Bindings {
+    fn enter_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    // Close the innermost scope, reporting the lets in it that were never read
+    fn exit_scope(&mut self) {
+        for binding in self.scopes.pop().unwrap_or_default() {
+            // A leading underscore marks a variable as deliberately unused
+            if binding.from_let && !binding.read && !binding.name.starts_with('_') {
+                self.warnings.push(Warning {
+                    message: format!(
+                        "Unused variable: '{}' is declared but never read",
+                        binding.name
+                    ),
+                    line: binding.line,
+                });
+            }
+        }
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<&mut Binding> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.iter_mut().rev().find(|b| b.name == name))
+    }
+
+    // Declare a variable. `let_line` is the line of the `let` (or `once`) it comes from; other
+    // variables, like parameters and loop variables, go on the top-level statement's line
+    fn declare(&mut self, name: &str, let_line: Option<usize>) {
+        if let Some(line) = let_line {
+            if let Some(earlier) = self.lookup(name) {
+                let message = format!(
+                    "Shadowed variable: '{}' hides the '{}' declared on line {}",
+                    name, name, earlier.line
+                );
+                self.warnings.push(Warning { message, line });
+            }
+        }
+        let binding = Binding {
+            name: name.to_string(),
+            line: let_line.unwrap_or(self.line),
+            from_let: let_line.is_some(),
+            read: false,
+        };
+        self.scopes.last_mut().unwrap().push(binding);
+    }
+
+    // Check a block in a scope of its own, with some variables already declared in it
+    fn visit_scoped_block(&mut self, declared: &[&str], body: &[Stmt]) {
+        self.enter_scope();
+        for name in declared {
+            self.declare(name, None);
+        }
+        self.visit_block(body);
+        self.exit_scope();
+    }
+}
+
+impl Visitor for Bindings {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            // The initializer runs before the new variable exists, so `let x = x + 1;`
+            // reads the outer x
+            Stmt::Let(_, name, expr, line) | Stmt::Once(_, _, name, expr, line) => {
+                self.visit_expr(expr);
+                self.declare(name, Some(*line));
+            }
+            Stmt::LetDestructure(names, expr, line) => {
+                self.visit_expr(expr);
+                for name in names {
+                    self.declare(name, Some(*line));
+                }
+            }
+            Stmt::Block(body) => self.visit_scoped_block(&[], body),
+            Stmt::While(cond, body) => {
+                self.visit_expr(cond);
+                self.visit_scoped_block(&[], body);
+            }
+            // The init's variable belongs to the enclosing scope; only the body is fresh
+            Stmt::For(init, cond, update, body) => {
+                if let Some(init) = init {
+                    self.visit_stmt(init);
+                }
+                if let Some(cond) = cond {
+                    self.visit_expr(cond);
+                }
+                if let Some(update) = update {
+                    self.visit_stmt(update);
+                }
+                self.visit_scoped_block(&[], body);
+            }
+            Stmt::ForEach(first, second, subject, body) => {
+                self.visit_expr(subject);
+                let mut declared = vec![first.as_str()];
+                declared.extend(second.as_deref());
+                self.visit_scoped_block(&declared, body);
+            }
+            Stmt::Try(body, catch, finally) => {
+                self.visit_scoped_block(&[], body);
+                if let Some((error_name, handler)) = catch {
+                    self.visit_scoped_block(&[error_name.as_str()], handler);
+                }
+                if let Some(cleanup) = finally {
+                    self.visit_scoped_block(&[], cleanup);
+                }
+            }
+            // Functions run in an interpreter of their own, so the body starts from nothing
+            // but its parameters
+            Stmt::FunctionDef(_, params, rest, _, body) => {
+                let outer = std::mem::take(&mut self.scopes);
+                let mut declared: Vec<&str> =
+                    params.iter().map(|(name, _)| name.as_str()).collect();
+                declared.extend(rest.as_ref().map(|(name, _)| name.as_str()));
+                self.visit_scoped_block(&declared, body);
+                self.scopes = outer;
+            }
+            // debug; shows every variable in scope, so it counts as reading them all
+            Stmt::Debug => {
+                for binding in self.scopes.iter_mut().flatten() {
+                    binding.read = true;
+                }
+            }
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_var(&mut self, name: &str) {
+        if let Some(binding) = self.lookup(name) {
+            binding.read = true;
+        }
+    }
+}
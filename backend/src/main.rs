@@ -1,26 +1,232 @@
 use std::env;
 use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::thread;
 
 mod ast;
+mod builtins;
 mod interpreter;
+mod json;
 mod lexer;
+mod lint;
 mod parser;
+mod program;
+mod typecheck;
+mod visitor;
 
-use finnlang::run_finn_code;
+use finnlang::{
+    check_finn_code, run_finn_code_traced, run_finn_code_with_options, run_finn_tests_with_options,
+    ReplSession, RunOptions, RunOutput, TraceStep, RUN_STACK_SIZE,
+};
 
+// Every flag the CLI knows. --call is handled on its own, since the arguments after it
+// belong to the call
+const FLAGS: &[&str] = &[
+    "--repl",
+    "--test",
+    "--time",
+    "--trace",
+    "--debug",
+    "--big-ints",
+    "--strict",
+    "--typecheck",
+    "--implicit-declare",
+];
+
+// Programs run on a thread with a much bigger stack than the main thread's default
 fn main() {
+    let runner = thread::Builder::new()
+        .stack_size(RUN_STACK_SIZE)
+        .spawn(run_cli)
+        .expect("Failed to start the interpreter thread");
+    if runner.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+fn run_cli() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    // `finnlang file.finn --call name arg...` runs the file, then calls `name` with the rest of
+    // the command line as string arguments. Everything after --call belongs to the call
+    let call = match args.iter().position(|arg| arg == "--call") {
+        Some(i) => {
+            let mut call_args = args.split_off(i).into_iter().skip(1);
+            let Some(function) = call_args.next() else {
+                eprintln!("--call needs the name of a function to call");
+                std::process::exit(2);
+            };
+            Some((function, call_args.collect::<Vec<String>>()))
+        }
+        None => None,
+    };
+
+    // A mistyped flag would otherwise be silently ignored, or taken for the file name
+    if let Some(unknown) = args
+        .iter()
+        .find(|arg| arg.starts_with("--") && !FLAGS.contains(&arg.as_str()))
+    {
+        eprintln!("Unknown option {}", unknown);
+        std::process::exit(2);
+    }
+
+    let has_flag = |flag: &str| args.iter().any(|arg| arg == flag);
+
+    // color() only adds escape codes when they'll show up as colors. NO_COLOR set to anything
+    // but an empty string turns them off (https://no-color.org)
+    let colors =
+        io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+
+    // `finnlang --repl` starts an interactive session instead of running a file
+    if has_flag("--repl") {
+        repl(colors);
+        return;
+    }
+
+    // `finnlang --test file.finn` counts assertion results instead of stopping at the first failure
+    let test_mode = has_flag("--test");
+    // `finnlang --time file.finn` reports how long parsing and running took
+    let time_mode = has_flag("--time");
+    // `finnlang --trace file.finn` prints each statement and the variables it sees as it runs
+    let trace_mode = has_flag("--trace");
+
+    // Everything else changes how the program runs, and they all combine
+    let options = RunOptions {
+        // `--debug` makes each debug; statement print the variables in scope
+        debug: has_flag("--debug"),
+        // `--big-ints` gives exact int results of any size instead of overflow errors
+        big_ints: has_flag("--big-ints"),
+        // `--strict` makes `woof(f())` an error when f has no return type
+        strict: has_flag("--strict"),
+        // `--typecheck` reports every type error up front instead of running
+        typecheck: has_flag("--typecheck"),
+        // `--implicit-declare` lets `x = 5;` declare x when there's no x yet
+        implicit_declare: has_flag("--implicit-declare"),
+        // Whatever the flags, code run from the command line is trusted with the file system,
+        // for read_file and write_file. The REPL gets it too
+        file_access: true,
+        colors,
+        call,
+        ..RunOptions::default()
+    };
+
     // Get filename from args, or use fallback
-    let filename = env::args()
-        .nth(1)
+    let filename = args
+        .iter()
+        .find(|arg| !arg.starts_with("--"))
+        .cloned()
         .unwrap_or_else(|| "example.finn".to_string());
 
     // Read code from file
     let source = fs::read_to_string(&filename).expect("Failed to read the .finn source file");
 
+    // Errors are printed below (and try/catch may handle them first), so skip Rust's
+    // default panic message
+    std::panic::set_hook(Box::new(|_| {}));
+
+    // Warnings don't stop the program. Parse errors are reported when it runs instead
+    for warning in check_finn_code(&source).unwrap_or_default() {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if test_mode {
+        run_tests(&source, &options);
+        return;
+    }
+
     // Run the interpreter and print the result
-    let result = run_finn_code(&source);
+    let (result, timings) = if trace_mode {
+        run_finn_code_traced(&source, &options, print_step)
+    } else {
+        run_finn_code_with_options(&source, &options)
+    };
     match result {
-        Ok(output) => println!("{}", output),
+        Ok(output) => print_output(&output),
         Err(error) => eprintln!("{}", error),
     }
+
+    // Timings go to stderr so they don't mix with the program's output
+    if time_mode {
+        eprintln!("Parse: {:.3} ms", timings.parse.as_secs_f64() * 1000.0);
+        eprintln!("Run:   {:.3} ms", timings.run.as_secs_f64() * 1000.0);
+    }
+}
+
+// Run a file in test mode, print a pass/fail summary, and exit nonzero on any failure
+fn run_tests(source: &str, options: &RunOptions) {
+    let report = match run_finn_tests_with_options(source, options).0 {
+        Ok(report) => report,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    print_output(&report.output);
+    for failure in &report.failures {
+        println!("FAIL: {}", failure);
+    }
+    println!("{} passed, {} failed", report.passed, report.failures.len());
+    if !report.failures.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+// One line of --trace output, on stderr so it doesn't mix with the program's output
+fn print_step(step: &TraceStep) {
+    let variables: Vec<String> = step
+        .variables
+        .iter()
+        .map(|(name, value)| format!("{} = {}", name, value))
+        .collect();
+    let line = format!("trace: {:<10} {}", step.statement, variables.join(", "));
+    eprintln!("{}", line.trim_end());
+}
+
+// woof() output goes to stdout and howl() output goes to stderr
+fn print_output(output: &RunOutput) {
+    if !output.stdout.is_empty() {
+        println!("{}", output.stdout);
+    }
+    if !output.stderr.is_empty() {
+        eprintln!("{}", output.stderr);
+    }
+}
+
+// Read-eval-print loop: bare expressions like `x + 1;` print their value
+fn repl(colors: bool) {
+    println!("FinnLang REPL 🐕 (Ctrl+D to exit)");
+    // Errors are printed nicely below, so skip Rust's default panic message
+    std::panic::set_hook(Box::new(|_| {}));
+    let mut session = ReplSession::new();
+    session.set_file_access(true);
+    session.set_colors(colors);
+    let stdin = io::stdin();
+    let mut input = String::new();
+
+    print!("finn> ");
+    io::stdout().flush().ok();
+    for line in stdin.lock().lines() {
+        let line = line.expect("Failed to read from stdin");
+        input.push_str(&line);
+        input.push('\n');
+
+        // Keep reading while a block is still open, e.g. halfway through a function
+        let depth = input.matches('{').count() as i64 - input.matches('}').count() as i64;
+        if depth > 0 {
+            print!("...   ");
+            io::stdout().flush().ok();
+            continue;
+        }
+
+        match session.run(&input) {
+            Ok(output) => print_output(&output),
+            Err(error) => eprintln!("{}", error),
+        }
+        input.clear();
+
+        print!("finn> ");
+        io::stdout().flush().ok();
+    }
+    println!();
 }
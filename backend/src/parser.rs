@@ -1,11 +1,33 @@
 #![allow(dead_code)]
 
-use crate::ast::{Expr, Stmt, Type};
+use crate::ast::{Accessor, Expr, LValue, Stmt, Type};
 use crate::lexer::{Lexer, Token};
+use crate::lint::Warning;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// How deeply statements and expressions may nest inside each other, e.g. ((((x)))) or
+// if (a) { if (b) { ... } }. The parser recurses for each level, so without a limit a
+// pathological input could overflow the stack, which can't be caught like a normal error
+pub const MAX_NESTING_DEPTH: usize = 128;
+
+// The id for the next once declaration parsed. Counting across every parse keeps ids unique
+// even between separately parsed REPL inputs run by the same interpreter
+static NEXT_ONCE_ID: AtomicUsize = AtomicUsize::new(0);
+
+// The cases a typematch can have: the type names Value::type_name gives (a ref is matched by
+// what it holds), and default
+const TYPEMATCH_CASES: &[&str] = &[
+    "int", "double", "bool", "string", "array", "map", "null", "default",
+];
 
 pub struct Parser {
     lexer: Lexer,
     current: Token,
+    // Current nesting depth and the most allowed
+    depth: usize,
+    max_depth: usize,
+    // Tokens that didn't start a statement and were skipped, one warning each
+    skipped: Vec<Warning>,
 }
 
 // This is the FinnLang parser
@@ -13,12 +35,111 @@ impl Parser {
     // Create a new parser instance from a lexer
     pub fn new(mut lexer: Lexer) -> Self {
         let current = lexer.next_token();
-        Parser { lexer, current }
+        let parser = Parser {
+            lexer,
+            current,
+            depth: 0,
+            max_depth: MAX_NESTING_DEPTH,
+            skipped: Vec::new(),
+        };
+        parser.reject_unknown();
+        parser
+    }
+
+    // Change how deeply statements and expressions may nest
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    // Run `parse` one nesting level deeper, failing with a parse error past the limit. The
+    // depth goes back to what it was afterwards, including any levels `parse` added itself
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> T) -> T {
+        let depth = self.depth;
+        self.deeper();
+        let result = parse(self);
+        self.depth = depth;
+        result
+    }
+
+    // Go one level deeper, failing with a parse error past the limit. Besides `nested`, chains
+    // like `a + b + c` and `a[0][1].b` call this for each operator, index or field: they're
+    // parsed in a loop, not by recursing, but each link wraps everything before it in one
+    // more node, and running or checking the result recurses through every node. A chain's
+    // parse function puts the depth back once it's done
+    fn deeper(&mut self) {
+        if self.depth >= self.max_depth {
+            let (line, column) = self.lexer.token_position();
+            panic!(
+                "Expression nesting too deep (more than {} levels) at line {}, column {}",
+                self.max_depth, line, column
+            );
+        }
+        self.depth += 1;
     }
 
     // Advance to the next token
     fn advance(&mut self) {
         self.current = self.lexer.next_token();
+        self.reject_unknown();
+    }
+
+    // The token after the current one, without consuming anything
+    fn peek_token(&self) -> Token {
+        self.lexer.clone().next_token()
+    }
+
+    // Stray characters the lexer didn't recognise are a hard error instead of being skipped
+    fn reject_unknown(&self) {
+        if let Token::Unknown(c) = self.current {
+            let (line, column) = self.lexer.token_position();
+            panic!(
+                "Unexpected character '{}' at line {}, column {}",
+                c, line, column
+            );
+        }
+    }
+
+    // Error or unexpected token: skip it, noting where, and carry on with the next statement.
+    // Running out of code instead is noted on `statement_line`, where the unfinished
+    // statement started, since the end of the file may be lines further down
+    fn skip_token(&mut self, statement_line: usize) {
+        let (line, column) = self.lexer.token_position();
+        let warning = if self.current == Token::EOF {
+            Warning {
+                message: "The code ends partway through a statement, so that statement was skipped"
+                    .to_string(),
+                line: statement_line,
+            }
+        } else {
+            Warning {
+                message: format!(
+                    "Skipped {:?} at column {}, which doesn't fit where it is, so the code around it may not run as written",
+                    self.current, column
+                ),
+                line,
+            }
+        };
+        self.skipped.push(warning);
+        self.advance();
+    }
+
+    // The warnings for tokens skipped so far
+    pub fn take_diagnostics(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.skipped)
+    }
+
+    // Parse the entire input, pairing each top-level statement with the line it starts on
+    pub fn parse_with_lines(&mut self) -> Vec<(usize, Stmt)> {
+        let mut stmts = Vec::new();
+        while self.current != Token::EOF {
+            let (line, _) = self.lexer.token_position();
+            if let Some(stmt) = self.parse_stmt() {
+                stmts.push((line, stmt));
+            } else {
+                self.skip_token(line);
+            }
+        }
+        stmts
     }
 
     // Parse the entire input and return a vector of statements
@@ -27,55 +148,89 @@ impl Parser {
         let mut stmts = Vec::new();
         while self.current != Token::EOF {
             // If we can parse a statement, add it to the list
+            let (line, _) = self.lexer.token_position();
             if let Some(stmt) = self.parse_stmt() {
                 stmts.push(stmt);
             } else {
-                // Error or unexpected token, skip it
-                self.advance();
+                self.skip_token(line);
             }
         }
         stmts
     }
 
-    // Parse a single statement based on the current token
+    // Parse a single statement, which may contain more nested inside it
     fn parse_stmt(&mut self) -> Option<Stmt> {
+        self.nested(Self::parse_stmt_kind)
+    }
+
+    // Parse a single statement based on the current token
+    fn parse_stmt_kind(&mut self) -> Option<Stmt> {
         match &self.current {
             Token::Let => self.parse_let_stmt(),
+            Token::Once => self.parse_once_stmt(),
             Token::Print => self.parse_print_stmt(),
+            Token::PrintErr => self.parse_print_stmt(),
             Token::While => self.parse_while_stmt(),
             Token::For => self.parse_for_stmt(),
             Token::If => self.parse_if_stmt(),
             Token::Funct => self.parse_function_def(),
             Token::Return => self.parse_return_stmt(),
-            Token::Ident(_) => {
-                // Look ahead to see if there's an assignment operator
-                if self.is_assignment() {
-                    self.parse_assign_stmt()
-                } else {
-                    self.parse_expr_stmt()
-                }
-            }
+            Token::Debug => self.parse_debug_stmt(),
+            Token::LBrace => self.parse_block().map(Stmt::Block),
+            Token::Try => self.parse_try_stmt(),
+            Token::TypeMatch => self.parse_typematch_stmt(),
+            Token::Assert | Token::AssertEq => self.parse_assert_stmt(),
+            // An assignment (x = ..., arr[i] = ..., p.name = ...) or an expression statement.
+            // Anything else that can start an expression starts an expression statement too,
+            // e.g. 5 + 3; or -x; (a '{' here is a block, not a map literal)
+            Token::Ident(_)
+            | Token::Number(_)
+            | Token::Double(_)
+            | Token::BoolLiteral(_)
+            | Token::StrLiteral(_)
+            | Token::Null
+            | Token::Int
+            | Token::LParen
+            | Token::LBracket
+            | Token::Minus
+            | Token::Not => self.parse_expr_stmt(),
             _ => None,
         }
     }
 
-    // Helper method to check if current identifier is part of an assignment
-    fn is_assignment(&self) -> bool {
-        // Look at the current identifier and peek ahead
-        if let Token::Ident(_) = &self.current {
-            // Create a temporary lexer to peek ahead
-            let mut temp_lexer = self.lexer.clone();
-            temp_lexer.next_token(); // skip the identifier
-            let next_token = temp_lexer.next_token();
-            matches!(next_token, Token::Assign)
-        } else {
-            false
+    /**
+     * This is for parsing a braced block of statements
+     * e.g. { let x = 5; woof(x); }
+     */
+    fn parse_block(&mut self) -> Option<Vec<Stmt>> {
+        if self.current != Token::LBrace {
+            return None;
         }
+        // consume '{'
+        self.advance();
+
+        let mut body = Vec::new();
+        while self.current != Token::RBrace && self.current != Token::EOF {
+            if let Some(stmt) = self.parse_stmt() {
+                body.push(stmt);
+            } else {
+                // skip unexpected tokens inside block
+                self.advance();
+            }
+        }
+
+        if self.current != Token::RBrace {
+            return None;
+        }
+        // consume '}'
+        self.advance();
+
+        Some(body)
     }
 
     // Parse expression statement: expr;
     fn parse_expr_stmt(&mut self) -> Option<Stmt> {
-        let expr = self.parse_expr()?;
+        let stmt = self.parse_simple_stmt()?;
 
         // Expect semicolon
         if self.current != Token::Semicolon {
@@ -83,7 +238,37 @@ impl Parser {
         }
         self.advance();
 
-        Some(Stmt::ExprStmt(expr))
+        Some(stmt)
+    }
+
+    // Parse an assignment, compound assignment or expression without its semicolon, as in a
+    // statement or a for loop's init and update: x = 1, x += 2, f(x)
+    fn parse_simple_stmt(&mut self) -> Option<Stmt> {
+        // The target of an assignment parses like any other expression until we reach the '='
+        let expr = self.parse_expr()?;
+        let operator: fn(Box<Expr>, Box<Expr>) -> Expr = match self.current {
+            Token::Assign => {
+                self.advance(); // consume '='
+                let value = self.parse_expr()?;
+                return Some(Stmt::Assign(into_lvalue(expr), value));
+            }
+            Token::PlusAssign => Expr::Add,
+            Token::MinusAssign => Expr::Sub,
+            Token::StarAssign => Expr::Mul,
+            Token::SlashAssign => Expr::Div,
+            Token::PercentAssign => Expr::Mod,
+            _ => return Some(Stmt::ExprStmt(expr)),
+        };
+        self.advance(); // consume the operator
+
+        // x += y is shorthand for x = x + y: the target is read and then written, but the
+        // interpreter only runs the index expressions in it once
+        let value = self.parse_expr()?;
+        let target = into_lvalue(expr.clone());
+        Some(Stmt::CompoundAssign(
+            target,
+            operator(Box::new(expr), Box::new(value)),
+        ))
     }
     /**
      * Parse the let variable keyword
@@ -93,6 +278,63 @@ impl Parser {
         // consume 'let'
         self.advance();
 
+        // let [a, b] = expr; destructures an array
+        if self.current == Token::LBracket {
+            return self.parse_let_destructure();
+        }
+
+        let stmt = self.parse_let_bindings()?;
+
+        // Expect semicolon
+        if self.current != Token::Semicolon {
+            return None;
+        }
+        // consume ';'
+        self.advance();
+
+        Some(stmt)
+    }
+
+    // Parse a once declaration: once name = expr; or once name: type = expr;
+    fn parse_once_stmt(&mut self) -> Option<Stmt> {
+        // consume 'once'
+        self.advance();
+        let Stmt::Let(var_type, var_name, expr, line) = self.parse_let_binding()? else {
+            unreachable!("parse_let_binding only gives a Let");
+        };
+
+        // Expect semicolon
+        if self.current != Token::Semicolon {
+            return None;
+        }
+        self.advance();
+
+        let id = NEXT_ONCE_ID.fetch_add(1, Ordering::Relaxed);
+        Some(Stmt::Once(id, var_type, var_name, expr, line))
+    }
+
+    /**
+     * Parse the comma-separated declarations after 'let'. One gives a Let; several give a
+     * LetGroup whose initializers run in order, so later ones can use earlier variables
+     * e.g. x: int = 5 or a = 1, b = a + 1
+     */
+    fn parse_let_bindings(&mut self) -> Option<Stmt> {
+        let mut lets = vec![self.parse_let_binding()?];
+        while self.current == Token::Comma {
+            // consume ','
+            self.advance();
+            lets.push(self.parse_let_binding()?);
+        }
+        if lets.len() == 1 {
+            lets.pop()
+        } else {
+            Some(Stmt::LetGroup(lets))
+        }
+    }
+
+    // Parse one declaration: name, optional type and initializer, e.g. x: int = 5
+    fn parse_let_binding(&mut self) -> Option<Stmt> {
+        let (line, _) = self.lexer.token_position();
         // Expect identifier
         let var_name = if let Token::Ident(name) = &self.current {
             name.clone()
@@ -106,10 +348,10 @@ impl Parser {
         let var_type = if self.current == Token::Colon {
             // consume ':'
             self.advance();
-            self.parse_type()?
+            Some(self.parse_type()?)
         } else {
-            // Default type, e.g. Int or a placeholder
-            Type::Int
+            // No annotation: the type is inferred from the initializer at runtime
+            None
         };
 
         // Expect '='
@@ -122,22 +364,68 @@ impl Parser {
         // Parse expression
         let expr = self.parse_expr()?;
 
-        // Expect semicolon
+        Some(Stmt::Let(var_type, var_name, expr, line))
+    }
+
+    /**
+     * This is for parsing array destructuring after 'let'
+     * e.g. let [x, y] = f();
+     */
+    fn parse_let_destructure(&mut self) -> Option<Stmt> {
+        let (line, _) = self.lexer.token_position();
+        // consume '['
+        self.advance();
+
+        let mut names = Vec::new();
+        while self.current != Token::RBracket {
+            if let Token::Ident(name) = &self.current {
+                names.push(name.clone());
+            } else {
+                return None;
+            }
+            self.advance();
+
+            if self.current == Token::Comma {
+                self.advance();
+            } else if self.current != Token::RBracket {
+                return None;
+            }
+        }
+        // consume ']'
+        self.advance();
+
+        if self.current != Token::Assign {
+            return None;
+        }
+        // consume '='
+        self.advance();
+
+        let expr = self.parse_expr()?;
+
         if self.current != Token::Semicolon {
             return None;
         }
         // consume ';'
         self.advance();
 
-        Some(Stmt::Let(var_type, var_name, expr))
+        Some(Stmt::LetDestructure(names, expr, line))
     }
 
     /**
      * This will parse a data type
-     * e.g. Int, Bool, String, Double
+     * e.g. Int, Bool, String, Double, or an array type like [int] or [[double]]
      */
     fn parse_type(&mut self) -> Option<Type> {
         match &self.current {
+            Token::LBracket => {
+                self.advance(); // consume '['
+                let element = self.parse_type()?;
+                if self.current != Token::RBracket {
+                    panic!("Expected ']' to close array type");
+                }
+                self.advance(); // consume ']'
+                Some(Type::Array(Box::new(element)))
+            }
             Token::Int => {
                 self.advance();
                 Some(Type::Int)
@@ -154,16 +442,22 @@ impl Parser {
                 self.advance();
                 Some(Type::Double)
             }
+            // Not a keyword, so ref(x) still calls the builtin
+            Token::Ident(name) if name == "ref" => {
+                self.advance();
+                Some(Type::Ref)
+            }
             _ => None,
         }
     }
 
     /**
-     * This is for parsing the woof() statement
+     * This is for parsing the woof() statement, and howl() which prints to stderr
      * e.g. woof(x);
      */
     fn parse_print_stmt(&mut self) -> Option<Stmt> {
-        // consume 'print'
+        let to_stderr = self.current == Token::PrintErr;
+        // consume 'woof' or 'howl'
         self.advance();
 
         if self.current != Token::LParen {
@@ -186,7 +480,51 @@ impl Parser {
         // consume ';'
         self.advance();
 
-        Some(Stmt::Print(expr))
+        if to_stderr {
+            Some(Stmt::PrintErr(expr))
+        } else {
+            Some(Stmt::Print(expr))
+        }
+    }
+
+    /**
+     * Parse assert(condition[, message]); or assert_eq(actual, expected[, message]);
+     */
+    fn parse_assert_stmt(&mut self) -> Option<Stmt> {
+        let is_eq = self.current == Token::AssertEq;
+        let keyword = if is_eq { "assert_eq" } else { "assert" };
+        // consume 'assert' or 'assert_eq'
+        self.advance();
+
+        if self.current != Token::LParen {
+            panic!("Expected '(' after {}", keyword);
+        }
+        self.advance();
+        let mut args = Vec::new();
+        while self.current != Token::RParen {
+            args.push(self.parse_expr()?);
+            if self.current == Token::Comma {
+                self.advance();
+            } else if self.current != Token::RParen {
+                panic!("Expected ',' or ')' in {}", keyword);
+            }
+        }
+        // consume ')'
+        self.advance();
+
+        if self.current != Token::Semicolon {
+            return None;
+        }
+        // consume ';'
+        self.advance();
+
+        let mut args = args.into_iter();
+        match (is_eq, args.len()) {
+            (false, 1 | 2) => Some(Stmt::Assert(args.next()?, args.next())),
+            (true, 2 | 3) => Some(Stmt::AssertEq(args.next()?, args.next()?, args.next())),
+            (false, n) => panic!("Expected 1 or 2 arguments to assert, got {}", n),
+            (true, n) => panic!("Expected 2 or 3 arguments to assert_eq, got {}", n),
+        }
     }
 
     /**
@@ -334,36 +672,58 @@ impl Parser {
 
     // Parse an expression
     fn parse_expr(&mut self) -> Option<Expr> {
-        self.parse_or_expr()
+        self.nested(Self::parse_coalesce_expr)
+    }
+
+    // Parse the ?? expression (lowest precedence)
+    fn parse_coalesce_expr(&mut self) -> Option<Expr> {
+        let depth = self.depth;
+        let mut left = self.parse_or_expr()?;
+        while self.current == Token::NullCoalesce {
+            self.advance();
+            self.deeper();
+            let right = self.parse_or_expr()?;
+            left = Expr::Coalesce(Box::new(left), Box::new(right));
+        }
+        self.depth = depth;
+        Some(left)
     }
 
     // Parse the OR expression
     fn parse_or_expr(&mut self) -> Option<Expr> {
+        let depth = self.depth;
         let mut left = self.parse_and_expr()?;
         while self.current == Token::Or {
             self.advance();
+            self.deeper();
             let right = self.parse_and_expr()?;
             left = Expr::Or(Box::new(left), Box::new(right));
         }
+        self.depth = depth;
         Some(left)
     }
 
     // Parse the AND expression
     fn parse_and_expr(&mut self) -> Option<Expr> {
+        let depth = self.depth;
         let mut left = self.parse_equality_expr()?;
         while self.current == Token::And {
             self.advance();
+            self.deeper();
             let right = self.parse_equality_expr()?;
             left = Expr::And(Box::new(left), Box::new(right));
         }
+        self.depth = depth;
         Some(left)
     }
     // Parse the equality expression
     fn parse_equality_expr(&mut self) -> Option<Expr> {
+        let depth = self.depth;
         let mut left = self.parse_rel_expr()?;
         while self.current == Token::Eq || self.current == Token::Neq {
             let op = self.current.clone();
             self.advance();
+            self.deeper();
             let right = self.parse_rel_expr()?;
             left = match op {
                 Token::Eq => Expr::Eq(Box::new(left), Box::new(right)),
@@ -371,10 +731,12 @@ impl Parser {
                 _ => unreachable!(),
             };
         }
+        self.depth = depth;
         Some(left)
     }
     // This is for parsing different comparison operators
     fn parse_rel_expr(&mut self) -> Option<Expr> {
+        let depth = self.depth;
         let mut left = self.parse_add_expr()?;
 
         while self.current == Token::LessThan
@@ -384,6 +746,7 @@ impl Parser {
         {
             let op = self.current.clone();
             self.advance();
+            self.deeper();
 
             let right = self.parse_add_expr()?;
 
@@ -396,14 +759,17 @@ impl Parser {
             };
         }
 
+        self.depth = depth;
         Some(left)
     }
     // This is for parsing the addition sign
     fn parse_add_expr(&mut self) -> Option<Expr> {
+        let depth = self.depth;
         let mut left = self.parse_mul_expr()?;
         while self.current == Token::Plus || self.current == Token::Minus {
             let op = self.current.clone();
             self.advance();
+            self.deeper();
             let right = self.parse_mul_expr()?;
             left = match op {
                 Token::Plus => Expr::Add(Box::new(left), Box::new(right)),
@@ -411,10 +777,12 @@ impl Parser {
                 _ => unreachable!(),
             };
         }
+        self.depth = depth;
         Some(left)
     }
     // This is for parsing the multiplication sign, and handles division and modulo
     fn parse_mul_expr(&mut self) -> Option<Expr> {
+        let depth = self.depth;
         let mut left = self.parse_unary_expr()?;
         while self.current == Token::Star
             || self.current == Token::Slash
@@ -422,6 +790,7 @@ impl Parser {
         {
             let op = self.current.clone();
             self.advance();
+            self.deeper();
             let right = self.parse_unary_expr()?;
             left = match op {
                 Token::Star => Expr::Mul(Box::new(left), Box::new(right)),
@@ -430,22 +799,65 @@ impl Parser {
                 _ => unreachable!(),
             };
         }
+        self.depth = depth;
         Some(left)
     }
     // This is for parsing the unary expressions
     fn parse_unary_expr(&mut self) -> Option<Expr> {
         if self.current == Token::Not {
             self.advance();
-            let expr = self.parse_unary_expr()?;
+            let expr = self.nested(Self::parse_unary_expr)?;
             Some(Expr::Not(Box::new(expr)))
         } else if self.current == Token::Minus {
             self.advance();
-            let expr = self.parse_unary_expr()?;
+            let expr = self.nested(Self::parse_unary_expr)?;
             Some(Expr::Neg(Box::new(expr)))
         } else {
             self.parse_term()
         }
     }
+    /**
+     * Parse a map literal. Keys are identifiers or string literals
+     * e.g. { name: "Finn", "favourite toy": "ball" }
+     */
+    fn parse_map_literal(&mut self) -> Option<Expr> {
+        self.advance(); // consume '{'
+        let mut pairs = Vec::new();
+        while self.current != Token::RBrace {
+            let key = match self.current.clone() {
+                Token::Ident(name) | Token::StrLiteral(name) => name,
+                _ => panic!("Expected a name or string as a map key"),
+            };
+            self.advance();
+            if self.current != Token::Colon {
+                panic!("Expected ':' after map key '{}'", key);
+            }
+            self.advance(); // consume ':'
+            pairs.push((key, self.parse_expr()?));
+
+            if self.current == Token::Comma {
+                self.advance();
+            } else if self.current != Token::RBrace {
+                panic!("Expected ',' or '}}' in map literal");
+            }
+        }
+        self.advance(); // consume '}'
+        Some(Expr::MapLiteral(pairs))
+    }
+
+    /**
+     * Parse one call argument or array literal element, which may be spread
+     * e.g. x + 1 or ...items
+     */
+    fn parse_list_item(&mut self) -> Option<Expr> {
+        if self.current == Token::Ellipsis {
+            self.advance(); // consume '...'
+            let expr = self.parse_expr()?;
+            Some(Expr::Spread(Box::new(expr)))
+        } else {
+            self.parse_expr()
+        }
+    }
     // this is for parsing different terms
     fn parse_term(&mut self) -> Option<Expr> {
         let term = match &self.current {
@@ -469,48 +881,34 @@ impl Parser {
                 self.advance();
                 Some(expr)
             }
+            Token::Null => {
+                self.advance();
+                Some(Expr::Null)
+            }
             Token::Ident(name) => {
                 let name = name.clone();
                 self.advance();
 
                 // Check if it's a function call (followed by '(')
                 if self.current == Token::LParen {
-                    self.advance(); // consume '('
-
-                    let mut args = Vec::new();
-
-                    // Parse arguments
-                    if self.current != Token::RParen {
-                        loop {
-                            let arg = self.parse_expr()?;
-                            args.push(arg);
-
-                            if self.current == Token::Comma {
-                                self.advance();
-                            } else {
-                                break;
-                            }
-                        }
-                    }
-
-                    // Expect ')'
-                    if self.current != Token::RParen {
-                        return None;
-                    }
-                    self.advance();
-
-                    Some(Expr::FunctionCall(name, args))
+                    self.parse_call_args(name)
                 } else {
                     // Just a variable reference
                     Some(Expr::Var(name))
                 }
             }
+            // int(x) converts to an int. `int` is also the type keyword, so it's only a call
+            // when a '(' follows
+            Token::Int if self.peek_token() == Token::LParen => {
+                self.advance(); // consume 'int'
+                self.parse_call_args("int".to_string())
+            }
             Token::LBracket => {
                 self.advance(); // consume '['
                 let mut elements = Vec::new();
                 if self.current != Token::RBracket {
                     loop {
-                        let expr = self.parse_expr()?;
+                        let expr = self.parse_list_item()?;
                         elements.push(expr);
                         if self.current == Token::Comma {
                             self.advance();
@@ -525,6 +923,7 @@ impl Parser {
                 self.advance(); // consume ']'
                 Some(Expr::ArrayLiteral(elements))
             }
+            Token::LBrace => self.parse_map_literal(),
             Token::LParen => {
                 self.advance();
                 let expr = self.parse_expr()?;
@@ -540,15 +939,46 @@ impl Parser {
         self.parse_postfix(expr)
     }
 
+    // Parse the parenthesised arguments of a call to `name`, starting at the '('
+    fn parse_call_args(&mut self, name: String) -> Option<Expr> {
+        self.advance(); // consume '('
+
+        let mut args = Vec::new();
+
+        // Parse arguments
+        if self.current != Token::RParen {
+            loop {
+                let arg = self.parse_list_item()?;
+                args.push(arg);
+
+                if self.current == Token::Comma {
+                    self.advance();
+                } else {
+                    break;
+                }
+            }
+        }
+
+        // Expect ')'
+        if self.current != Token::RParen {
+            return None;
+        }
+        self.advance();
+
+        Some(Expr::FunctionCall(name, args))
+    }
+
     /**
      * This is for parsing postfix expressions
-     * e.g. array indexing arr[0]
+     * e.g. array indexing arr[0] and field access p.name, in any order
      */
     fn parse_postfix(&mut self, mut expr: Expr) -> Option<Expr> {
+        let depth = self.depth;
         loop {
             match &self.current {
                 Token::LBracket => {
                     self.advance(); // consume '['
+                    self.deeper();
                     let index = self.parse_expr()?;
                     if self.current != Token::RBracket {
                         panic!("Expected closing bracket for index");
@@ -556,38 +986,51 @@ impl Parser {
                     self.advance(); // consume ']'
                     expr = Expr::Index(Box::new(expr), Box::new(index));
                 }
-                _ => break,
+                Token::Dot => {
+                    self.advance(); // consume '.'
+                    self.deeper();
+                    let Token::Ident(field) = self.current.clone() else {
+                        panic!("Expected a field name after '.'");
+                    };
+                    self.advance();
+                    expr = Expr::Field(Box::new(expr), field);
+                }
+                _ => {
+                    self.depth = depth;
+                    return Some(expr);
+                }
             }
         }
-        Some(expr)
     }
 
-    // This is for parsing assignment operators
-    fn parse_assign_stmt(&mut self) -> Option<Stmt> {
-        // Assume current is Token::Ident
-        let name = if let Token::Ident(n) = &self.current {
-            n.clone()
+    // Parse the rest of a for-each loop after 'for (': x in expr) { body } or
+    // key, value in expr) { body }
+    fn parse_for_each(&mut self, first: String) -> Option<Stmt> {
+        self.advance(); // consume the first variable
+        let second = if self.current == Token::Comma {
+            self.advance();
+            let Token::Ident(second) = self.current.clone() else {
+                panic!("Expected a second variable name after ',' in for-each loop");
+            };
+            self.advance();
+            Some(second)
         } else {
-            return None;
+            None
         };
-        // consume identifier
-        self.advance();
 
-        if self.current != Token::Assign {
-            return None;
+        if self.current != Token::Ident("in".to_string()) {
+            panic!("Expected 'in' after the for-each loop variables");
         }
-        // consume '='
         self.advance();
 
-        let expr = self.parse_expr()?;
-
-        if self.current != Token::Semicolon {
-            return None;
+        let subject = self.parse_expr()?;
+        if self.current != Token::RParen {
+            panic!("Expected ')' after the for-each subject");
         }
-        // consume ';'
         self.advance();
 
-        Some(Stmt::Assign(name, expr))
+        let body = self.parse_block()?;
+        Some(Stmt::ForEach(first, second, subject, body))
     }
 
     // Parse for loop: for (init; condition; update) { body }
@@ -601,6 +1044,15 @@ impl Parser {
         }
         self.advance();
 
+        // `for (x in ...)` and `for (k, v in ...)` are for-each loops. `in` is only special here,
+        // so it can still be used as a variable name elsewhere
+        if let Token::Ident(first) = self.current.clone() {
+            let next = self.peek_token();
+            if next == Token::Comma || next == Token::Ident("in".to_string()) {
+                return self.parse_for_each(first);
+            }
+        }
+
         // Parse init statement (optional)
         let init = if self.current == Token::Semicolon {
             None
@@ -608,8 +1060,7 @@ impl Parser {
             // Parse init statement (typically let or assignment) - handle semicolon manually
             let init_stmt = match &self.current {
                 Token::Let => self.parse_let_stmt_no_semicolon(),
-                Token::Ident(_) => self.parse_assign_stmt_no_semicolon(),
-                _ => return None,
+                _ => self.parse_simple_stmt(),
             };
             init_stmt.map(Box::new)
         };
@@ -637,12 +1088,8 @@ impl Parser {
         let update = if self.current == Token::RParen {
             None
         } else {
-            // Parse update (typically assignment) - no semicolon in for loop
-            if let Token::Ident(_) = &self.current {
-                self.parse_assign_stmt_no_semicolon().map(Box::new)
-            } else {
-                return None;
-            }
+            // Any assignment or expression, e.g. i = i + 1, i += 2 or step(), with no semicolon
+            self.parse_simple_stmt().map(Box::new)
         };
 
         // Expect ')'
@@ -698,8 +1145,9 @@ impl Parser {
         }
         self.advance();
 
-        // Parse parameters
+        // Parse parameters, the last of which may be a rest parameter, e.g. nums: int...
         let mut params = Vec::new();
+        let mut rest = None;
         while self.current != Token::RParen {
             // Parse parameter name
             let param_name = if let Token::Ident(n) = &self.current {
@@ -707,6 +1155,14 @@ impl Parser {
             } else {
                 return None;
             };
+            // A second parameter with the same name would overwrite the first one's argument
+            if params.iter().any(|(seen, _)| *seen == param_name) {
+                let (line, column) = self.lexer.token_position();
+                panic!(
+                    "Duplicate parameter '{}' in function {} at line {}, column {}",
+                    param_name, name, line, column
+                );
+            }
             self.advance();
 
             // Expect ':'
@@ -717,6 +1173,19 @@ impl Parser {
 
             // Parse parameter type
             let param_type = self.parse_type()?;
+
+            // A rest parameter collects any remaining arguments, so nothing can follow it
+            if self.current == Token::Ellipsis {
+                self.advance(); // consume '...'
+                if self.current != Token::RParen {
+                    panic!(
+                        "Expected ')' after rest parameter '{}': it must be the last parameter",
+                        param_name
+                    );
+                }
+                rest = Some((param_name, param_type));
+                break;
+            }
             params.push((param_name, param_type));
 
             // Check for comma or end
@@ -738,6 +1207,17 @@ impl Parser {
             None
         };
 
+        // funct f(x: int): int => expr; returns the single expression. The ';' is optional
+        if self.current == Token::Arrow {
+            self.advance(); // consume '=>'
+            let expr = self.parse_expr()?;
+            if self.current == Token::Semicolon {
+                self.advance();
+            }
+            let body = vec![Stmt::Return(Some(expr))];
+            return Some(Stmt::FunctionDef(name, params, rest, return_type, body));
+        }
+
         // Expect '{'
         if self.current != Token::LBrace {
             return None;
@@ -760,7 +1240,7 @@ impl Parser {
         }
         self.advance();
 
-        Some(Stmt::FunctionDef(name, params, return_type, body))
+        Some(Stmt::FunctionDef(name, params, rest, return_type, body))
     }
 
     // Parse return statement: return expr;
@@ -784,67 +1264,159 @@ impl Parser {
         Some(Stmt::Return(expr))
     }
 
-    // Helper methods for for-loop parsing that don't consume semicolons
-    fn parse_let_stmt_no_semicolon(&mut self) -> Option<Stmt> {
-        // consume 'let'
+    // Parse a debug statement: debug;
+    fn parse_debug_stmt(&mut self) -> Option<Stmt> {
+        // consume 'debug'
         self.advance();
 
-        // Expect identifier
-        let var_name = if let Token::Ident(name) = &self.current {
-            name.clone()
-        } else {
+        // Expect semicolon
+        if self.current != Token::Semicolon {
             return None;
-        };
-        // consume identifier
+        }
         self.advance();
 
-        // This is for the optional colon + type syntax
-        let var_type = if self.current == Token::Colon {
-            // consume ':'
+        Some(Stmt::Debug)
+    }
+
+    /**
+     * Parse a try statement with an optional catch and/or finally clause
+     * e.g. try { risky(); } catch (e) { woof(e); } finally { woof("done"); }
+     */
+    fn parse_try_stmt(&mut self) -> Option<Stmt> {
+        // consume 'try'
+        self.advance();
+        let body = self.parse_block()?;
+
+        let catch = if self.current == Token::Catch {
+            self.advance();
+            if self.current != Token::LParen {
+                panic!("Expected '(' after catch");
+            }
+            self.advance();
+            let Token::Ident(name) = self.current.clone() else {
+                panic!("Expected a variable name for the error in catch (...)");
+            };
+            self.advance();
+            if self.current != Token::RParen {
+                panic!("Expected ')' after catch variable");
+            }
             self.advance();
-            self.parse_type()?
+            Some((name, self.parse_block()?))
         } else {
-            // Default type, e.g. Int or a placeholder
-            Type::Int
+            None
         };
 
-        // Expect '='
-        if self.current != Token::Assign {
-            return None;
-        }
-        // consume '='
-        self.advance();
-
-        // Parse expression
-        let expr = self.parse_expr()?;
+        let finally = if self.current == Token::Finally {
+            self.advance();
+            Some(self.parse_block()?)
+        } else {
+            None
+        };
 
-        // Don't consume semicolon here
-        Some(Stmt::Let(var_type, var_name, expr))
+        if catch.is_none() && finally.is_none() {
+            panic!("Expected catch or finally after try block");
+        }
+        Some(Stmt::Try(body, catch, finally))
     }
 
     /**
-     * This is for parsing assignment statements without consuming the semicolon
-     * e.g. x = 10
+     * Parse a typematch statement: the subject, then braces holding `type: { ... }` cases and
+     * an optional `default: { ... }`, in any order, each at most once
+     * e.g. typematch x { int: { woof("int"); } default: { woof("other"); } }
      */
-    fn parse_assign_stmt_no_semicolon(&mut self) -> Option<Stmt> {
-        // Assume current is Token::Ident
-        let name = if let Token::Ident(n) = &self.current {
-            n.clone()
-        } else {
-            return None;
-        };
-        // consume identifier
+    fn parse_typematch_stmt(&mut self) -> Option<Stmt> {
+        // consume 'typematch'
+        self.advance();
+        let subject = self.parse_expr()?;
+        if self.current != Token::LBrace {
+            panic!("Expected '{{' after the typematch subject");
+        }
         self.advance();
 
-        if self.current != Token::Assign {
-            return None;
+        let mut cases: Vec<(String, Vec<Stmt>)> = Vec::new();
+        let mut default = None;
+        while self.current != Token::RBrace {
+            let (line, column) = self.lexer.token_position();
+            // Types with a keyword of their own lex as that keyword, the rest as names
+            let case = match &self.current {
+                Token::Int => "int",
+                Token::DoubleType => "double",
+                Token::Bool => "bool",
+                Token::StringType => "string",
+                Token::Null => "null",
+                Token::Ident(name) if TYPEMATCH_CASES.contains(&name.as_str()) => {
+                    TYPEMATCH_CASES.iter().find(|case| *case == name).unwrap()
+                }
+                Token::EOF => panic!("Expected '}}' to close typematch"),
+                Token::Ident(name) => panic!(
+                    "Unknown typematch case '{}' at line {}, column {}: the cases are {}",
+                    name,
+                    line,
+                    column,
+                    TYPEMATCH_CASES.join(", ")
+                ),
+                other => panic!(
+                    "Expected a type name as a typematch case at line {}, column {}, got {:?}",
+                    line, column, other
+                ),
+            };
+            let repeated = if case == "default" {
+                default.is_some()
+            } else {
+                cases.iter().any(|(seen, _)| seen == case)
+            };
+            if repeated {
+                panic!(
+                    "Duplicate typematch case '{}' at line {}, column {}",
+                    case, line, column
+                );
+            }
+            self.advance();
+            if self.current != Token::Colon {
+                panic!("Expected ':' after typematch case '{}'", case);
+            }
+            self.advance();
+            let body = self.parse_block()?;
+            if case == "default" {
+                default = Some(body);
+            } else {
+                cases.push((case.to_string(), body));
+            }
         }
-        // consume '='
+        // consume '}'
         self.advance();
 
-        let expr = self.parse_expr()?;
+        Some(Stmt::TypeMatch(subject, cases, default))
+    }
+
+    // Helper methods for for-loop parsing that don't consume semicolons
+    fn parse_let_stmt_no_semicolon(&mut self) -> Option<Stmt> {
+        // consume 'let'
+        self.advance();
 
         // Don't consume semicolon here
-        Some(Stmt::Assign(name, expr))
+        self.parse_let_bindings()
+    }
+}
+
+// Turn the expression on the left of '=' into the place it names. Only a variable followed by
+// indices and fields can be assigned to
+fn into_lvalue(expr: Expr) -> LValue {
+    match expr {
+        Expr::Var(name) => LValue {
+            name,
+            path: Vec::new(),
+        },
+        Expr::Index(target, index) => {
+            let mut lvalue = into_lvalue(*target);
+            lvalue.path.push(Accessor::Index(*index));
+            lvalue
+        }
+        Expr::Field(target, field) => {
+            let mut lvalue = into_lvalue(*target);
+            lvalue.path.push(Accessor::Field(field));
+            lvalue
+        }
+        _ => panic!("Expected a variable, index or field on the left of '='"),
     }
 }
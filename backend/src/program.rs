@@ -0,0 +1,115 @@
+// Fix linting warnings for unused code during development
+#![allow(dead_code)]
+// A parsed program together with what's known about it before it runs: where each top-level
+// statement starts, the warnings from parsing and the static checks, and the names it
+// declares. Tools that want to look at a program without running it start from here
+
+use std::collections::HashMap;
+
+use crate::ast::Stmt;
+use crate::lint::{self, Warning};
+use crate::typecheck::{self, TypeError};
+use crate::visitor::{CallCounter, Visitor};
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    // Top-level statements paired with the line each one starts on
+    pub statements: Vec<(usize, Stmt)>,
+    // Code the parser had to skip and the static checks' warnings, in line order
+    pub diagnostics: Vec<Warning>,
+    // Length of the source in characters
+    pub source_len: usize,
+    // Functions and variables declared at the top level, in the order they're declared
+    pub symbols: Vec<Symbol>,
+    // Everything the type checker found. A run with RunOptions::typecheck won't start a
+    // program with any of these; without it, running code doesn't check types
+    pub type_errors: Vec<TypeError>,
+}
+
+// A name declared at the top level of a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // Line of the statement that declares it
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Variable,
+}
+
+impl Program {
+    // Gather everything about freshly parsed statements. `parse_diagnostics` are the parser's
+    // own warnings, which go in with the static checks' ones
+    pub(crate) fn new(
+        source: &str,
+        statements: Vec<(usize, Stmt)>,
+        mut parse_diagnostics: Vec<Warning>,
+    ) -> Self {
+        let mut diagnostics = lint::check_program(&statements);
+        diagnostics.append(&mut parse_diagnostics);
+        // Stable, so the checks' warnings still come before the parser's on the same line
+        diagnostics.sort_by_key(|warning| warning.line);
+
+        let mut symbols = Vec::new();
+        for (line, stmt) in &statements {
+            declare_symbols(stmt, *line, &mut symbols);
+        }
+
+        Program {
+            type_errors: typecheck::check_types(&statements),
+            statements,
+            diagnostics,
+            source_len: source.chars().count(),
+            symbols,
+        }
+    }
+
+    // The first top-level declaration of `name`, if there is one
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    // How many times each function (user-defined or builtin) is called anywhere in the
+    // program, counting calls inside function bodies and arguments
+    pub fn call_counts(&self) -> HashMap<String, usize> {
+        let mut counter = CallCounter::default();
+        for (_, stmt) in &self.statements {
+            counter.visit_stmt(stmt);
+        }
+        counter.counts
+    }
+
+    // Just the statements, ready to run
+    pub fn into_statements(self) -> Vec<Stmt> {
+        self.statements.into_iter().map(|(_, stmt)| stmt).collect()
+    }
+}
+
+// Add the names a top-level statement declares. Names declared inside blocks are local to
+// them, so they aren't symbols of the program
+fn declare_symbols(stmt: &Stmt, line: usize, symbols: &mut Vec<Symbol>) {
+    let symbol = |name: &String, kind| Symbol {
+        name: name.clone(),
+        kind,
+        line,
+    };
+    match stmt {
+        Stmt::Let(_, name, ..) | Stmt::Once(_, _, name, ..) => {
+            symbols.push(symbol(name, SymbolKind::Variable))
+        }
+        Stmt::LetDestructure(names, ..) => {
+            symbols.extend(names.iter().map(|name| symbol(name, SymbolKind::Variable)))
+        }
+        Stmt::FunctionDef(name, ..) => symbols.push(symbol(name, SymbolKind::Function)),
+        Stmt::LetGroup(lets) => {
+            for stmt in lets {
+                declare_symbols(stmt, line, symbols);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,437 @@
+// Fix linting warnings for unused code during development
+#![allow(dead_code)]
+// Static type checking before a program runs. It works out the types it can be sure of from
+// the source (literals, typed lets and parameters, functions with a return type, and the
+// operators that combine them) and reports every operation on them that can only fail, and
+// every value that doesn't match a type written in the program. Anything it can't be sure of,
+// like an untyped variable, a builtin's result or a map entry, is assumed to be fine, so each
+// error it reports is a real one
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{Accessor, Expr, Stmt, Type};
+use crate::visitor::{walk_block, walk_expr, walk_stmt, Visitor};
+
+// A type mismatch, with the line of the top-level statement it's in (like a lint Warning)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub message: String,
+    pub line: usize,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (line {})", self.message, self.line)
+    }
+}
+
+// Check a whole program (top-level statements paired with their lines), returning every type
+// error in the order they appear
+pub fn check_types(program: &[(usize, Stmt)]) -> Vec<TypeError> {
+    let mut signatures = Signatures::default();
+    for (_, stmt) in program {
+        signatures.visit_stmt(stmt);
+    }
+
+    let mut checker = TypeChecker {
+        signatures: signatures.found,
+        scopes: vec![HashMap::new()],
+        ..TypeChecker::default()
+    };
+    for (line, stmt) in program {
+        checker.line = *line;
+        checker.visit_stmt(stmt);
+    }
+    checker.errors
+}
+
+// The types written on a function's parameters, its rest parameter and its return
+#[derive(Clone)]
+struct Signature {
+    params: Vec<Type>,
+    rest: Option<Type>,
+    return_type: Option<Type>,
+}
+
+// Collects the signature of every function defined anywhere in the program. A name defined
+// more than once gets None, since which definition a call reaches depends on the run
+#[derive(Default)]
+struct Signatures {
+    found: HashMap<String, Option<Signature>>,
+}
+
+impl Visitor for Signatures {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::FunctionDef(name, params, rest, return_type, _) = stmt {
+            let signature = Signature {
+                params: params.iter().map(|(_, ty)| ty.clone()).collect(),
+                rest: rest.as_ref().map(|(_, ty)| ty.clone()),
+                return_type: return_type.clone(),
+            };
+            self.found
+                .entry(name.clone())
+                .and_modify(|earlier| *earlier = None)
+                .or_insert(Some(signature));
+        }
+        walk_stmt(self, stmt);
+    }
+}
+
+// Scopes follow the interpreter's, like lint's Bindings: every block has its own, and a
+// function body starts from nothing but its parameters
+#[derive(Default)]
+struct TypeChecker {
+    // Line of the top-level statement being checked
+    line: usize,
+    signatures: HashMap<String, Option<Signature>>,
+    // The type of each variable in scope, or None when it isn't known
+    scopes: Vec<HashMap<String, Option<Type>>>,
+    // Name and return type of the function whose body is being checked
+    function: Option<(String, Option<Type>)>,
+    errors: Vec<TypeError>,
+}
+
+impl TypeChecker {
+    fn error(&mut self, message: String) {
+        self.errors.push(TypeError {
+            message,
+            line: self.line,
+        });
+    }
+
+    // Variables declared as ref can hold anything and are read through, so their type is
+    // never known
+    fn declare(&mut self, name: &str, ty: Option<Type>) {
+        let ty = ty.filter(|ty| *ty != Type::Ref);
+        self.scopes.last_mut().unwrap().insert(name.to_string(), ty);
+    }
+
+    fn lookup(&self, name: &str) -> Option<Type> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .cloned()
+            .flatten()
+    }
+
+    // Check a block in a scope of its own, with some variables already declared in it
+    fn visit_scoped_block(&mut self, declared: Vec<(&str, Option<Type>)>, body: &[Stmt]) {
+        self.scopes.push(HashMap::new());
+        for (name, ty) in declared {
+            self.declare(name, ty);
+        }
+        walk_block(self, body);
+        self.scopes.pop();
+    }
+
+    // The type of an expression, if it's certain, checking everything inside it on the way
+    fn infer(&mut self, expr: &Expr) -> Option<Type> {
+        match expr {
+            Expr::Number(_) => Some(Type::Int),
+            Expr::Double(_) => Some(Type::Double),
+            Expr::Bool(_) => Some(Type::Bool),
+            Expr::StrLiteral(_) => Some(Type::String),
+            Expr::Var(name) => self.lookup(name),
+            Expr::FunctionCall(name, args) => self.infer_call(name, args),
+            // An array whose elements all have the same known type
+            Expr::ArrayLiteral(elements) => {
+                let types: Vec<Option<Type>> = elements.iter().map(|e| self.infer(e)).collect();
+                let spread = elements.iter().any(|e| matches!(e, Expr::Spread(_)));
+                match types.first() {
+                    Some(Some(first))
+                        if !spread && types.iter().all(|ty| ty.as_ref() == Some(first)) =>
+                    {
+                        Some(Type::Array(Box::new(first.clone())))
+                    }
+                    _ => None,
+                }
+            }
+            Expr::Index(target, index) => {
+                let target = self.infer(target);
+                let index = self.infer(index);
+                match (target, index) {
+                    (Some(Type::Array(_)), Some(index)) if index != Type::Int => {
+                        self.error(format!("Array index must be an int, got {}", index));
+                        None
+                    }
+                    (Some(Type::Array(element)), _) => Some(*element),
+                    _ => None,
+                }
+            }
+            Expr::Add(left, right) => {
+                let (left, right) = (self.infer(left), self.infer(right));
+                match (left, right) {
+                    // Adding anything to a string joins their printed forms
+                    (Some(Type::String), _) | (_, Some(Type::String)) => Some(Type::String),
+                    (left, right) => self.numeric("addition", left, right),
+                }
+            }
+            Expr::Sub(left, right) => self.arithmetic("subtraction", left, right),
+            Expr::Mul(left, right) => self.arithmetic("multiplication", left, right),
+            Expr::Div(left, right) => self.arithmetic("division", left, right),
+            Expr::Mod(left, right) => self.arithmetic("modulo", left, right),
+            Expr::LessThan(left, right) => self.ordering("<", left, right),
+            Expr::GreaterThan(left, right) => self.ordering(">", left, right),
+            Expr::LessEqual(left, right) => self.ordering("<=", left, right),
+            Expr::GreaterEqual(left, right) => self.ordering(">=", left, right),
+            // Any two values can be compared for equality
+            Expr::Eq(left, right) | Expr::Neq(left, right) => {
+                self.infer(left);
+                self.infer(right);
+                Some(Type::Bool)
+            }
+            Expr::And(left, right) => {
+                self.expect_bool("and", left);
+                self.expect_bool("and", right);
+                Some(Type::Bool)
+            }
+            Expr::Or(left, right) => {
+                self.expect_bool("or", left);
+                self.expect_bool("or", right);
+                Some(Type::Bool)
+            }
+            Expr::Not(inner) => {
+                self.expect_bool("not", inner);
+                Some(Type::Bool)
+            }
+            Expr::Neg(inner) => match self.infer(inner) {
+                Some(ty @ (Type::Int | Type::Double)) => Some(ty),
+                Some(other) => {
+                    self.error(format!("Unsupported negation type: {}", other));
+                    None
+                }
+                None => None,
+            },
+            // Either side can be the result, so only a shared type is certain
+            Expr::Coalesce(left, right) => {
+                let left = self.infer(left);
+                let right = self.infer(right);
+                left.filter(|left| right.as_ref() == Some(left))
+            }
+            Expr::Null | Expr::MapLiteral(_) | Expr::Spread(_) | Expr::Field(_, _) => {
+                walk_expr(self, expr);
+                None
+            }
+        }
+    }
+
+    // -, *, / and %: two ints or two doubles, never anything else
+    fn arithmetic(&mut self, operation: &str, left: &Expr, right: &Expr) -> Option<Type> {
+        let (left, right) = (self.infer(left), self.infer(right));
+        self.numeric(operation, left, right)
+    }
+
+    fn numeric(
+        &mut self,
+        operation: &str,
+        left: Option<Type>,
+        right: Option<Type>,
+    ) -> Option<Type> {
+        match (left, right) {
+            (Some(Type::Int), Some(Type::Int)) => Some(Type::Int),
+            (Some(Type::Double), Some(Type::Double)) => Some(Type::Double),
+            (Some(left), Some(right)) => {
+                self.error(format!(
+                    "Unsupported {} types: {} and {}",
+                    operation, left, right
+                ));
+                None
+            }
+            // One side alone is enough to be sure when it isn't a number at all
+            (Some(known), None) | (None, Some(known))
+                if !matches!(known, Type::Int | Type::Double) && operation != "addition" =>
+            {
+                self.error(format!("Unsupported {} type: {}", operation, known));
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // <, >, <= and >= compare two ints or two doubles
+    fn ordering(&mut self, operator: &str, left: &Expr, right: &Expr) -> Option<Type> {
+        let (left, right) = (self.infer(left), self.infer(right));
+        let numeric = |ty: &Type| matches!(ty, Type::Int | Type::Double);
+        let fits = match (&left, &right) {
+            (Some(left), Some(right)) => left == right && numeric(left),
+            (Some(known), None) | (None, Some(known)) => numeric(known),
+            (None, None) => true,
+        };
+        if !fits {
+            let describe = |ty: Option<Type>| ty.map_or("unknown".to_string(), |ty| ty.to_string());
+            self.error(format!(
+                "Unsupported types for {} comparison: {} and {}",
+                operator,
+                describe(left),
+                describe(right)
+            ));
+        }
+        Some(Type::Bool)
+    }
+
+    fn expect_bool(&mut self, operation: &str, expr: &Expr) {
+        match self.infer(expr) {
+            Some(Type::Bool) | None => {}
+            Some(other) => self.error(format!("{} expects a bool, got {}", operation, other)),
+        }
+    }
+
+    // A call to a user function is checked against its signature: the number of arguments and
+    // the type of each one. Builtins aren't checked here, and their results aren't known
+    fn infer_call(&mut self, name: &str, args: &[Expr]) -> Option<Type> {
+        let arg_types: Vec<Option<Type>> = args.iter().map(|arg| self.infer(arg)).collect();
+        let signature = self.signatures.get(name).cloned().flatten()?;
+        // A spread argument can stand for any number of arguments of any type
+        if args.iter().any(|arg| matches!(arg, Expr::Spread(_))) {
+            return signature.return_type;
+        }
+
+        let fixed = signature.params.len();
+        if signature.rest.is_some() && args.len() < fixed {
+            self.error(format!(
+                "Function {} expects at least {} arguments, got {}",
+                name,
+                fixed,
+                args.len()
+            ));
+        } else if signature.rest.is_none() && args.len() != fixed {
+            self.error(format!(
+                "Function {} expects {} arguments, got {}",
+                name,
+                fixed,
+                args.len()
+            ));
+        } else {
+            // Arguments past the fixed parameters go into the rest parameter
+            let expected = signature.params.iter().chain(signature.rest.iter().cycle());
+            for (i, (arg, expected)) in arg_types.iter().zip(expected).enumerate() {
+                if let Some(arg) = arg {
+                    if arg != expected && *expected != Type::Ref {
+                        self.error(format!(
+                            "Argument {} of {} should be {}, got {}",
+                            i + 1,
+                            name,
+                            expected,
+                            arg
+                        ));
+                    }
+                }
+            }
+        }
+        signature.return_type
+    }
+}
+
+impl Visitor for TypeChecker {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            // The initializer is checked before the new variable exists, like it runs
+            Stmt::Let(declared, name, expr, _) | Stmt::Once(_, declared, name, expr, _) => {
+                let actual = self.infer(expr);
+                if let (Some(declared), Some(actual)) = (declared, actual) {
+                    if actual != *declared && *declared != Type::Ref {
+                        self.error(format!(
+                            "Variable {} is declared as {}, but its value is {}",
+                            name, declared, actual
+                        ));
+                    }
+                }
+                self.declare(name, declared.clone());
+            }
+            Stmt::LetDestructure(names, expr, _) => {
+                self.infer(expr);
+                for name in names {
+                    self.declare(name, None);
+                }
+            }
+            Stmt::Assign(target, expr) | Stmt::CompoundAssign(target, expr) => {
+                // A compound assignment's value reads the target, so its indices are in there
+                if let Stmt::Assign(..) = stmt {
+                    for accessor in &target.path {
+                        if let Accessor::Index(index) = accessor {
+                            self.infer(index);
+                        }
+                    }
+                }
+                let actual = self.infer(expr);
+                if target.path.is_empty() {
+                    if let (Some(declared), Some(actual)) = (self.lookup(&target.name), actual) {
+                        if actual != declared {
+                            self.error(format!(
+                                "Cannot assign {} to {}, which is declared as {}",
+                                actual, target.name, declared
+                            ));
+                        }
+                    }
+                }
+            }
+            Stmt::Return(expr) => {
+                let actual = expr.as_ref().and_then(|expr| self.infer(expr));
+                if let Some((function, Some(return_type))) = self.function.clone() {
+                    match (expr, actual) {
+                        (None, _) => self.error(format!(
+                            "Function {} must return {}, but has a return without a value",
+                            function, return_type
+                        )),
+                        (Some(_), Some(actual)) if actual != return_type => self.error(format!(
+                            "Function {} must return {}, but returns {}",
+                            function, return_type, actual
+                        )),
+                        _ => {}
+                    }
+                }
+            }
+            // Looping over an array of a known type gives its elements that type, and looping
+            // over a string gives strings
+            Stmt::ForEach(first, second, subject, body) => {
+                let element = match self.infer(subject) {
+                    Some(Type::Array(element)) => Some(*element),
+                    Some(Type::String) => Some(Type::String),
+                    _ => None,
+                };
+                let declared = match second {
+                    Some(second) => {
+                        let index = element.as_ref().map(|_| Type::Int);
+                        vec![(first.as_str(), index), (second.as_str(), element)]
+                    }
+                    None => vec![(first.as_str(), element)],
+                };
+                self.visit_scoped_block(declared, body);
+            }
+            Stmt::Try(body, catch, finally) => {
+                self.visit_block(body);
+                if let Some((error_name, handler)) = catch {
+                    self.visit_scoped_block(vec![(error_name.as_str(), None)], handler);
+                }
+                if let Some(cleanup) = finally {
+                    self.visit_block(cleanup);
+                }
+            }
+            Stmt::FunctionDef(name, params, rest, return_type, body) => {
+                let outer_scopes = std::mem::take(&mut self.scopes);
+                let outer_function = self.function.replace((name.clone(), return_type.clone()));
+                let mut declared: Vec<(&str, Option<Type>)> = params
+                    .iter()
+                    .map(|(name, ty)| (name.as_str(), Some(ty.clone())))
+                    .collect();
+                if let Some((name, ty)) = rest {
+                    declared.push((name.as_str(), Some(Type::Array(Box::new(ty.clone())))));
+                }
+                self.visit_scoped_block(declared, body);
+                self.function = outer_function;
+                self.scopes = outer_scopes;
+            }
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        self.infer(expr);
+    }
+
+    fn visit_block(&mut self, stmts: &[Stmt]) {
+        self.visit_scoped_block(Vec::new(), stmts);
+    }
+}
@@ -0,0 +1,198 @@
+// Fix linting warnings for unused code during development
+#![allow(dead_code)]
+// A read-only walk over the AST for analysis passes (linting, counting, dead-code checks).
+// Implement `Visitor`, override the methods for the nodes you care about, and call back into
+// the matching `walk_*` function (or the default method) to keep recursing into children
+
+use std::collections::HashMap;
+
+use crate::ast::{Accessor, Expr, Stmt};
+
+pub trait Visitor {
+    // Every statement goes through here; the default visits its children
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    // Every expression goes through here; the default visits its children
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+
+    // A list of statements: the whole program, or the body of a function, loop, if or try
+    fn visit_block(&mut self, stmts: &[Stmt]) {
+        walk_block(self, stmts);
+    }
+
+    // funct name(...) { body }
+    fn visit_function_def(&mut self, _name: &str, body: &[Stmt]) {
+        self.visit_block(body);
+    }
+
+    // name(args), for user functions and builtins alike
+    fn visit_call(&mut self, _name: &str, args: &[Expr]) {
+        for arg in args {
+            self.visit_expr(arg);
+        }
+    }
+
+    // A variable being read
+    fn visit_var(&mut self, _name: &str) {}
+}
+
+// Visit each statement of a block in order
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, stmts: &[Stmt]) {
+    for stmt in stmts {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+// Visit the expressions and blocks directly inside a statement
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Let(_, _, expr, _)
+        | Stmt::Once(_, _, _, expr, _)
+        | Stmt::LetDestructure(_, expr, _)
+        | Stmt::Print(expr)
+        | Stmt::PrintErr(expr)
+        | Stmt::ExprStmt(expr) => visitor.visit_expr(expr),
+        // The value reads the target, so its indices are visited as part of it
+        Stmt::CompoundAssign(_, expr) => visitor.visit_expr(expr),
+        Stmt::Assign(target, expr) => {
+            for accessor in &target.path {
+                if let Accessor::Index(index) = accessor {
+                    visitor.visit_expr(index);
+                }
+            }
+            visitor.visit_expr(expr);
+        }
+        Stmt::Return(expr) => {
+            if let Some(expr) = expr {
+                visitor.visit_expr(expr);
+            }
+        }
+        Stmt::While(cond, body) => {
+            visitor.visit_expr(cond);
+            visitor.visit_block(body);
+        }
+        Stmt::For(init, cond, update, body) => {
+            if let Some(init) = init {
+                visitor.visit_stmt(init);
+            }
+            if let Some(cond) = cond {
+                visitor.visit_expr(cond);
+            }
+            if let Some(update) = update {
+                visitor.visit_stmt(update);
+            }
+            visitor.visit_block(body);
+        }
+        Stmt::ForEach(_, _, subject, body) => {
+            visitor.visit_expr(subject);
+            visitor.visit_block(body);
+        }
+        Stmt::If(cond, then_block, elifs, else_block) => {
+            visitor.visit_expr(cond);
+            visitor.visit_block(then_block);
+            for (elif_cond, elif_block) in elifs {
+                visitor.visit_expr(elif_cond);
+                visitor.visit_block(elif_block);
+            }
+            if let Some(else_block) = else_block {
+                visitor.visit_block(else_block);
+            }
+        }
+        Stmt::FunctionDef(name, _, _, _, body) => visitor.visit_function_def(name, body),
+        Stmt::Block(body) => visitor.visit_block(body),
+        // The Lets aren't a block of their own: they declare into the enclosing scope
+        Stmt::LetGroup(lets) => walk_block(visitor, lets),
+        Stmt::Assert(cond, message) => {
+            visitor.visit_expr(cond);
+            if let Some(message) = message {
+                visitor.visit_expr(message);
+            }
+        }
+        Stmt::AssertEq(actual, expected, message) => {
+            visitor.visit_expr(actual);
+            visitor.visit_expr(expected);
+            if let Some(message) = message {
+                visitor.visit_expr(message);
+            }
+        }
+        Stmt::TypeMatch(subject, cases, default) => {
+            visitor.visit_expr(subject);
+            for (_, body) in cases {
+                visitor.visit_block(body);
+            }
+            if let Some(default) = default {
+                visitor.visit_block(default);
+            }
+        }
+        Stmt::Try(body, catch, finally) => {
+            visitor.visit_block(body);
+            if let Some((_, handler)) = catch {
+                visitor.visit_block(handler);
+            }
+            if let Some(cleanup) = finally {
+                visitor.visit_block(cleanup);
+            }
+        }
+        Stmt::Debug => {}
+    }
+}
+
+// Visit the sub-expressions of an expression
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Number(_) | Expr::Bool(_) | Expr::StrLiteral(_) | Expr::Double(_) | Expr::Null => {}
+        Expr::Var(name) => visitor.visit_var(name),
+        Expr::FunctionCall(name, args) => visitor.visit_call(name, args),
+        Expr::ArrayLiteral(elements) => {
+            for element in elements {
+                visitor.visit_expr(element);
+            }
+        }
+        Expr::MapLiteral(pairs) => {
+            for (_, value) in pairs {
+                visitor.visit_expr(value);
+            }
+        }
+        Expr::Spread(inner) | Expr::Neg(inner) | Expr::Not(inner) | Expr::Field(inner, _) => {
+            visitor.visit_expr(inner)
+        }
+        Expr::Add(left, right)
+        | Expr::Sub(left, right)
+        | Expr::Mul(left, right)
+        | Expr::Div(left, right)
+        | Expr::Mod(left, right)
+        | Expr::Index(left, right)
+        | Expr::Eq(left, right)
+        | Expr::LessThan(left, right)
+        | Expr::GreaterThan(left, right)
+        | Expr::LessEqual(left, right)
+        | Expr::GreaterEqual(left, right)
+        | Expr::Neq(left, right)
+        | Expr::And(left, right)
+        | Expr::Or(left, right)
+        | Expr::Coalesce(left, right) => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+    }
+}
+
+// Example visitor: how many times each function is called anywhere in a program
+#[derive(Default)]
+pub struct CallCounter {
+    pub counts: HashMap<String, usize>,
+}
+
+impl Visitor for CallCounter {
+    fn visit_call(&mut self, name: &str, args: &[Expr]) {
+        *self.counts.entry(name.to_string()).or_insert(0) += 1;
+        // Calls nested in the arguments count too
+        for arg in args {
+            self.visit_expr(arg);
+        }
+    }
+}